@@ -0,0 +1,565 @@
+//! Assembler for the mnemonics documented on [`Instruction`](crate::Instruction).
+//!
+//! Source syntax, one statement per line:
+//!
+//! ```text
+//! ; comments start with a semicolon
+//! ROWS = 5                ; constant
+//!         org 0x200       ; place the following code at 0x200 (the default)
+//! start:  LD I digit      ; labels end with a colon
+//!         LD V0 10
+//!         DRW V0 V0 ROWS
+//! loop:   JP loop
+//! digit:  db 0xF0, 0x90, 0xF0, 0x90, 0xF0
+//!         dw 0x1234
+//! ```
+//!
+//! Operands are separated by spaces or commas. Numbers are decimal, hexadecimal
+//! (`0x1F`) or binary (`0b0101`), and may be combined with labels and constants
+//! using `+` and `-` (without spaces, e.g. `digit+5`). Mnemonics and register
+//! names are case-insensitive.
+//!
+//! A constant's value may only refer to labels and constants defined above it;
+//! instructions and data may refer to any label or constant.
+
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use thiserror::Error;
+
+use crate::instructions::{EncodeError, Instruction, MemAddr, RegId};
+use crate::memory::{Memory, MemoryError};
+use crate::progloader::PROGRAM_START;
+
+#[derive(Error, Debug)]
+#[error("line {line}: {kind}")]
+pub struct AssembleError {
+    pub line: usize,
+    pub kind: AssembleErrorKind,
+}
+
+#[derive(Error, Debug)]
+pub enum AssembleErrorKind {
+    #[error("unknown mnemonic {0:?}")]
+    UnknownMnemonic(String),
+    #[error("invalid operands for {0}")]
+    InvalidOperands(String),
+    #[error("invalid label or constant name {0:?}")]
+    InvalidName(String),
+    #[error("undefined label or constant {0:?}")]
+    Undefined(String),
+    #[error("label or constant {0:?} is already defined")]
+    Duplicate(String),
+    #[error("invalid number {0:?}")]
+    InvalidNumber(String),
+    #[error("value {value:#X} out of range (maximum {max:#X})")]
+    OutOfRange { value: i64, max: i64 },
+    #[error(transparent)]
+    Encode(#[from] EncodeError),
+    #[error("output overlaps previous output at {0:03X}")]
+    Overlap(MemAddr),
+}
+
+/// Assembled program: blocks of bytes at their addresses.
+#[derive(Debug, Clone, Default)]
+pub struct Program {
+    segments: Vec<(MemAddr, Vec<u8>)>,
+}
+
+impl Program {
+    /// Address of the first byte of the program, [`PROGRAM_START`] for an empty program
+    pub fn start(&self) -> MemAddr {
+        self.segments
+            .iter()
+            .map(|(addr, _)| *addr)
+            .min()
+            .unwrap_or(PROGRAM_START)
+    }
+
+    /// Raw binary image starting at [`Program::start`], with gaps filled with zeros
+    pub fn to_binary(&self) -> Vec<u8> {
+        let start = self.start();
+        let end = self
+            .segments
+            .iter()
+            .map(|(addr, bytes)| addr + bytes.len())
+            .max()
+            .unwrap_or(start);
+        let mut image = vec![0; end - start];
+        for (addr, bytes) in &self.segments {
+            image[(addr - start)..(addr - start + bytes.len())].copy_from_slice(bytes);
+        }
+        image
+    }
+
+    /// Program in the hex text format read by [`progloader::load_from_hex`](crate::progloader::load_from_hex)
+    pub fn to_hex(&self) -> String {
+        const LINE: usize = 16;
+        let mut hex = String::new();
+        for (addr, bytes) in &self.segments {
+            for (offset, chunk) in (0..bytes.len()).step_by(LINE).zip(bytes.chunks(LINE)) {
+                write!(hex, "{:04X}  ", addr + offset).unwrap();
+                for word in chunk.chunks(2) {
+                    hex.push(' ');
+                    for byte in word {
+                        write!(hex, "{:02X}", byte).unwrap();
+                    }
+                }
+                hex.push('\n');
+            }
+        }
+        hex
+    }
+
+    pub fn load(&self, mem: &mut Memory) -> Result<(), MemoryError> {
+        for (addr, bytes) in &self.segments {
+            mem.set_mem_from(*addr, bytes)?;
+        }
+        Ok(())
+    }
+}
+
+pub fn assemble(source: &str) -> Result<Program, AssembleError> {
+    let lines = parse(source)?;
+
+    // pass 1: assign addresses to labels
+    let mut symbols: HashMap<String, i64> = HashMap::new();
+    let mut addr = PROGRAM_START;
+    for line in &lines {
+        let define = |symbols: &mut HashMap<String, i64>, name: &str, value: i64| {
+            let key = name.to_ascii_lowercase();
+            if symbols.insert(key, value).is_some() {
+                return Err(line.error(AssembleErrorKind::Duplicate(name.to_string())));
+            }
+            Ok(())
+        };
+        if let Some(label) = line.label {
+            define(&mut symbols, label, addr as i64)?;
+        }
+        match &line.statement {
+            Some(Statement::Constant(name, expr)) => {
+                let value = evaluate(expr, &symbols).map_err(|kind| line.error(kind))?;
+                define(&mut symbols, name, value)?;
+            }
+            Some(Statement::Org(expr)) => {
+                let value = evaluate(expr, &symbols).map_err(|kind| line.error(kind))?;
+                addr = in_range(value, 0xFFFF).map_err(|kind| line.error(kind))? as MemAddr;
+            }
+            Some(statement) => addr += statement.size(),
+            None => {}
+        }
+    }
+
+    // pass 2: emit code and data
+    let mut program = Program::default();
+    let mut segment: Option<(MemAddr, Vec<u8>)> = None;
+    let mut segment_lines = Vec::new();
+    for line in &lines {
+        let mut bytes = Vec::new();
+        match &line.statement {
+            Some(Statement::Org(expr)) => {
+                let value = evaluate(expr, &symbols).map_err(|kind| line.error(kind))?;
+                if let Some(done) = segment.take() {
+                    program.segments.push(done);
+                }
+                segment = Some((value as MemAddr, Vec::new()));
+                segment_lines.push(line.number);
+            }
+            Some(Statement::Data { width, values }) => {
+                for value in values {
+                    let value = evaluate(value, &symbols).map_err(|kind| line.error(kind))?;
+                    if *width == 1 {
+                        bytes.push(in_range(value, 0xFF).map_err(|kind| line.error(kind))? as u8);
+                    } else {
+                        let word = in_range(value, 0xFFFF).map_err(|kind| line.error(kind))?;
+                        bytes.extend_from_slice(&(word as u16).to_be_bytes());
+                    }
+                }
+            }
+            Some(Statement::Instruction(mnemonic, operands)) => {
+                let words =
+                    encode(mnemonic, operands, &symbols).map_err(|kind| line.error(kind))?;
+                for word in words {
+                    bytes.extend_from_slice(&word.to_be_bytes());
+                }
+            }
+            Some(Statement::Constant(..)) | None => {}
+        }
+        if !bytes.is_empty() {
+            let (_, output) = segment.get_or_insert_with(|| {
+                segment_lines.push(line.number);
+                (PROGRAM_START, Vec::new())
+            });
+            output.extend(bytes);
+        }
+    }
+    if let Some(done) = segment.take() {
+        program.segments.push(done);
+    }
+
+    // only non-empty segments end up in the output, check they do not overlap
+    let mut used: Vec<(MemAddr, MemAddr, usize)> = program
+        .segments
+        .iter()
+        .zip(segment_lines)
+        .filter(|((_, bytes), _)| !bytes.is_empty())
+        .map(|((addr, bytes), line)| (*addr, addr + bytes.len(), line))
+        .collect();
+    used.sort();
+    for pair in used.windows(2) {
+        let (_, prev_end, _) = pair[0];
+        let (start, _, line) = pair[1];
+        if start < prev_end {
+            return Err(AssembleError {
+                line,
+                kind: AssembleErrorKind::Overlap(start),
+            });
+        }
+    }
+    program.segments.retain(|(_, bytes)| !bytes.is_empty());
+    Ok(program)
+}
+
+struct Line<'a> {
+    number: usize,
+    label: Option<&'a str>,
+    statement: Option<Statement<'a>>,
+}
+
+impl Line<'_> {
+    fn error(&self, kind: AssembleErrorKind) -> AssembleError {
+        AssembleError {
+            line: self.number,
+            kind,
+        }
+    }
+}
+
+enum Statement<'a> {
+    Org(&'a str),
+    Constant(&'a str, &'a str),
+    Data { width: usize, values: Vec<&'a str> },
+    Instruction(String, Vec<&'a str>),
+}
+
+impl Statement<'_> {
+    /// Size in bytes of the output
+    fn size(&self) -> usize {
+        match self {
+            Statement::Data { width, values } => width * values.len(),
+            Statement::Instruction(mnemonic, _) if mnemonic == "LDL" => 4,
+            Statement::Instruction(..) => 2,
+            Statement::Org(_) | Statement::Constant(..) => 0,
+        }
+    }
+}
+
+fn parse(source: &str) -> Result<Vec<Line<'_>>, AssembleError> {
+    let mut lines = Vec::new();
+    for (index, text) in source.lines().enumerate() {
+        let number = index + 1;
+        let error = |kind| AssembleError { line: number, kind };
+        let text = text.split(';').next().unwrap_or("").trim();
+        let (label, rest) = match text.split_once(':') {
+            Some((label, rest)) => (Some(label.trim()), rest.trim()),
+            None => (None, text),
+        };
+        if let Some(label) = label {
+            check_name(label).map_err(error)?;
+        }
+
+        let tokens: Vec<&str> = rest
+            .split(|c: char| c.is_whitespace() || c == ',')
+            .filter(|token| !token.is_empty())
+            .collect();
+        let statement = match tokens.as_slice() {
+            [] => None,
+            [name, eq, value] if *eq == "=" || eq.eq_ignore_ascii_case("equ") => {
+                check_name(name).map_err(error)?;
+                Some(Statement::Constant(name, value))
+            }
+            [org, addr] if org.eq_ignore_ascii_case("org") => Some(Statement::Org(addr)),
+            [db, values @ ..] if db.eq_ignore_ascii_case("db") => Some(Statement::Data {
+                width: 1,
+                values: values.to_vec(),
+            }),
+            [dw, values @ ..] if dw.eq_ignore_ascii_case("dw") => Some(Statement::Data {
+                width: 2,
+                values: values.to_vec(),
+            }),
+            [mnemonic, operands @ ..] => Some(Statement::Instruction(
+                mnemonic.to_ascii_uppercase(),
+                operands.to_vec(),
+            )),
+        };
+        lines.push(Line {
+            number,
+            label,
+            statement,
+        });
+    }
+    Ok(lines)
+}
+
+fn check_name(name: &str) -> Result<(), AssembleErrorKind> {
+    let mut chars = name.chars();
+    let valid = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+        && register(name).is_none()
+        && !name.eq_ignore_ascii_case("i");
+    if valid {
+        Ok(())
+    } else {
+        Err(AssembleErrorKind::InvalidName(name.to_string()))
+    }
+}
+
+/// Evaluates `term (('+' | '-') term)*`, where a term is a number or a symbol.
+fn evaluate(expr: &str, symbols: &HashMap<String, i64>) -> Result<i64, AssembleErrorKind> {
+    let mut total: i64 = 0;
+    let (mut sign, mut rest): (i64, &str) = match expr.strip_prefix('-') {
+        Some(rest) => (-1, rest),
+        None => (1, expr),
+    };
+    loop {
+        let end = rest.find(['+', '-']).unwrap_or(rest.len());
+        total = sign
+            .checked_mul(term(&rest[..end], symbols)?)
+            .and_then(|value| total.checked_add(value))
+            .ok_or_else(|| AssembleErrorKind::InvalidNumber(expr.to_string()))?;
+        if end == rest.len() {
+            return Ok(total);
+        }
+        sign = if rest.as_bytes()[end] == b'-' { -1 } else { 1 };
+        rest = &rest[(end + 1)..];
+    }
+}
+
+fn term(term: &str, symbols: &HashMap<String, i64>) -> Result<i64, AssembleErrorKind> {
+    let invalid = || AssembleErrorKind::InvalidNumber(term.to_string());
+    let lower = term.to_ascii_lowercase();
+    if let Some(hex) = lower.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).map_err(|_| invalid())
+    } else if let Some(bin) = lower.strip_prefix("0b") {
+        i64::from_str_radix(bin, 2).map_err(|_| invalid())
+    } else if term.is_empty() || term.starts_with(|c: char| c.is_ascii_digit()) {
+        term.parse().map_err(|_| invalid())
+    } else {
+        symbols
+            .get(&lower)
+            .copied()
+            .ok_or_else(|| AssembleErrorKind::Undefined(term.to_string()))
+    }
+}
+
+fn in_range(value: i64, max: i64) -> Result<i64, AssembleErrorKind> {
+    if (0..=max).contains(&value) {
+        Ok(value)
+    } else {
+        Err(AssembleErrorKind::OutOfRange { value, max })
+    }
+}
+
+fn register(token: &str) -> Option<RegId> {
+    let digit = token.strip_prefix(['v', 'V'])?;
+    if digit.len() != 1 {
+        return None;
+    }
+    usize::from_str_radix(digit, 16).ok()
+}
+
+enum Operand {
+    Reg(RegId),
+    I,
+    Value(i64),
+}
+
+fn operand(token: &str, symbols: &HashMap<String, i64>) -> Result<Operand, AssembleErrorKind> {
+    if let Some(reg) = register(token) {
+        Ok(Operand::Reg(reg))
+    } else if token.eq_ignore_ascii_case("i") {
+        Ok(Operand::I)
+    } else {
+        evaluate(token, symbols).map(Operand::Value)
+    }
+}
+
+fn encode(
+    mnemonic: &str,
+    operands: &[&str],
+    symbols: &HashMap<String, i64>,
+) -> Result<Vec<u16>, AssembleErrorKind> {
+    use Instruction::*;
+    use Operand::{Reg, Value, I};
+
+    let operands = operands
+        .iter()
+        .map(|token| operand(token, symbols))
+        .collect::<Result<Vec<_>, _>>()?;
+    let n = |v: i64| in_range(v, 0xF).map(|v| v as u8);
+    let nn = |v: i64| in_range(v, 0xFF).map(|v| v as u8);
+    let nnn = |v: i64| in_range(v, 0xFFF).map(|v| v as MemAddr);
+
+    let instr = match (mnemonic, operands.as_slice()) {
+        ("CLS", []) => DispClear,
+        ("RET", []) => Ret,
+        ("HALT", []) => Halt,
+        ("SYS", [Value(addr)]) => NoOp(nnn(*addr)? as u16),
+        ("SCD", [Value(v)]) => ScrollDown(n(*v)?),
+        ("SCU", [Value(v)]) => ScrollUp(n(*v)?),
+        ("SCR", []) => ScrollRight,
+        ("SCL", []) => ScrollLeft,
+        ("EXIT", []) => Exit,
+        ("LOW", []) => LoRes,
+        ("HIGH", []) => HiRes,
+        ("JP", [Value(addr)]) => Jump(nnn(*addr)?),
+        ("CALL", [Value(addr)]) => Call(nnn(*addr)?),
+        ("SE", [Reg(vx), Value(v)]) => SkipIfEqX(*vx, nn(*v)?),
+        ("SNE", [Reg(vx), Value(v)]) => SkipIfNeX(*vx, nn(*v)?),
+        ("SE", [Reg(vx), Reg(vy)]) => SkipIfEqXY(*vx, *vy),
+        ("SAVE", [Reg(vx), Reg(vy)]) => RegDumpRangeIXY(*vx, *vy),
+        ("LOAD", [Reg(vx), Reg(vy)]) => RegLoadRangeIXY(*vx, *vy),
+        ("LD", [Reg(vx), Value(v)]) => AssignXImm(*vx, nn(*v)?),
+        ("ADD", [Reg(vx), Value(v)]) => AddXImm(*vx, nn(*v)?),
+        ("LD", [Reg(vx), Reg(vy)]) => AssignXY(*vx, *vy),
+        ("OR", [Reg(vx), Reg(vy)]) => OrXY(*vx, *vy),
+        ("AND", [Reg(vx), Reg(vy)]) => AndXY(*vx, *vy),
+        ("XOR", [Reg(vx), Reg(vy)]) => XorXY(*vx, *vy),
+        ("ADD", [Reg(vx), Reg(vy)]) => AddXY(*vx, *vy),
+        ("SUB", [Reg(vx), Reg(vy)]) => SubXY(*vx, *vy),
+        ("SHR", [Reg(vx)]) => Shr1X(*vx, *vx),
+        ("SHR", [Reg(vx), Reg(vy)]) => Shr1X(*vx, *vy),
+        ("SUBN", [Reg(vx), Reg(vy)]) => SubYX(*vx, *vy),
+        ("SHL", [Reg(vx)]) => Shl1X(*vx, *vx),
+        ("SHL", [Reg(vx), Reg(vy)]) => Shl1X(*vx, *vy),
+        ("SNE", [Reg(vx), Reg(vy)]) => SkipIfNeXY(*vx, *vy),
+        ("LD", [I, Value(addr)]) => SetI(nnn(*addr)?),
+        ("JPV0", [Value(addr)]) => JumpV0(nnn(*addr)?),
+        ("RND", [Reg(vx), Value(v)]) => RandX(*vx, nn(*v)?),
+        ("DRW", [Reg(vx), Reg(vy), Value(v)]) => DispDraw(*vx, *vy, n(*v)?),
+        ("SKP", [Reg(vx)]) => SkipIfKeyEqX(*vx),
+        ("SKNP", [Reg(vx)]) => SkipIfKeyNeX(*vx),
+        ("LDL", [I, Value(addr)]) => SetILong(in_range(*addr, 0xFFFF)? as MemAddr),
+        ("PLANE", [Value(v)]) => SelectPlanes(n(*v)?),
+        ("AUDIO", []) => LoadAudio,
+        ("LDDT", [Reg(vx)]) => GetDelayX(*vx),
+        ("LDK", [Reg(vx)]) => AwaitKeyX(*vx),
+        ("STDT", [Reg(vx)]) => SetDelayX(*vx),
+        ("STST", [Reg(vx)]) => SetSoundX(*vx),
+        ("ADDI", [Reg(vx)]) => AddIX(*vx),
+        ("LDSPR", [Reg(vx)]) => SpriteAddrIX(*vx),
+        ("LDHSPR", [Reg(vx)]) => BigSpriteAddrIX(*vx),
+        ("STBCD", [Reg(vx)]) => DumpBcdIX(*vx),
+        ("PITCH", [Reg(vx)]) => SetPitchX(*vx),
+        ("STREGS", [Reg(vx)]) => RegDumpIX(*vx),
+        ("LDREGS", [Reg(vx)]) => RegLoadIX(*vx),
+        ("STRPL", [Reg(vx)]) => StoreFlagsX(*vx),
+        ("LDRPL", [Reg(vx)]) => LoadFlagsX(*vx),
+        (mnemonic, _) if MNEMONICS.contains(&mnemonic) => {
+            return Err(AssembleErrorKind::InvalidOperands(mnemonic.to_string()))
+        }
+        (mnemonic, _) => return Err(AssembleErrorKind::UnknownMnemonic(mnemonic.to_string())),
+    };
+    Ok(instr.encode_words()?)
+}
+
+const MNEMONICS: &[&str] = &[
+    "CLS", "RET", "HALT", "SYS", "SCD", "SCU", "SCR", "SCL", "EXIT", "LOW", "HIGH", "JP", "CALL",
+    "SE", "SNE", "SAVE", "LOAD", "LD", "ADD", "OR", "AND", "XOR", "SUB", "SHR", "SUBN", "SHL",
+    "JPV0", "RND", "DRW", "SKP", "SKNP", "LDL", "PLANE", "AUDIO", "LDDT", "LDK", "STDT", "STST",
+    "ADDI", "LDSPR", "LDHSPR", "STBCD", "PITCH", "STREGS", "LDREGS", "STRPL", "LDRPL",
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn binary(source: &str) -> Vec<u8> {
+        assemble(source).unwrap().to_binary()
+    }
+
+    fn error(source: &str) -> AssembleError {
+        assemble(source).unwrap_err()
+    }
+
+    #[test]
+    fn labels_and_forward_references() {
+        let source = "start: JP end\n\tCALL start\nend: JP start+2";
+        assert_eq!(binary(source), [0x12, 0x04, 0x22, 0x00, 0x12, 0x02]);
+    }
+
+    #[test]
+    fn constants() {
+        let source = "ROWS = 5\nX equ ROWS-2\nDRW V0 V1 ROWS\nLD V2 X+0x10";
+        assert_eq!(binary(source), [0xD0, 0x15, 0x62, 0x13]);
+        assert!(matches!(
+            error("A = B\nB = 1").kind,
+            AssembleErrorKind::Undefined(name) if name == "B"
+        ));
+    }
+
+    #[test]
+    fn data_directives() {
+        let source = "LD I data\ndata: db 0xF0, 0b1001, 3\ndw 0x1234, data";
+        assert_eq!(
+            binary(source),
+            [0xA2, 0x02, 0xF0, 0x09, 0x03, 0x12, 0x34, 0x02, 0x02]
+        );
+        assert!(matches!(
+            error("db 256").kind,
+            AssembleErrorKind::OutOfRange {
+                value: 256,
+                max: 0xFF
+            }
+        ));
+    }
+
+    #[test]
+    fn org_places_code_and_rejects_overlaps() {
+        let program = assemble("org 0x300\nCLS\norg 0x200\nRET").unwrap();
+        assert_eq!(program.start(), 0x200);
+        assert_eq!(program.to_binary().len(), 0x102);
+        let err = error("CLS\nCLS\norg 0x202\nRET");
+        assert_eq!(err.line, 3);
+        assert!(matches!(err.kind, AssembleErrorKind::Overlap(0x202)));
+    }
+
+    #[test]
+    fn errors_have_line_numbers() {
+        let err = error("CLS\n\n  FOO V0\n");
+        assert_eq!(err.line, 3);
+        assert!(matches!(err.kind, AssembleErrorKind::UnknownMnemonic(_)));
+        let err = error("CLS\nLD V0");
+        assert_eq!(err.line, 2);
+        assert!(matches!(err.kind, AssembleErrorKind::InvalidOperands(_)));
+        let err = error("a: CLS\nA: CLS");
+        assert_eq!(err.line, 2);
+        assert!(matches!(err.kind, AssembleErrorKind::Duplicate(_)));
+    }
+
+    #[test]
+    fn overflowing_expressions_are_rejected() {
+        for source in [
+            "org 0x7FFFFFFFFFFFFFFF+1",
+            "org -0x7FFFFFFFFFFFFFFF-2",
+            "A = -0x7FFFFFFFFFFFFFFF-1\nB = 0-A",
+        ] {
+            let err = error(source);
+            assert!(
+                matches!(err.kind, AssembleErrorKind::InvalidNumber(_)),
+                "{}: {}",
+                source,
+                err
+            );
+        }
+    }
+
+    #[test]
+    fn assembled_program_runs() {
+        let mut system = crate::tests::assembled("LD V0 0\nloop: ADD V0 1\nSE V0 5\nJP loop\nHALT");
+        system.run().unwrap();
+        assert_eq!(system.cpu().get_register(0), 5);
+    }
+}
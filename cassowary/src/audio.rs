@@ -0,0 +1,336 @@
+//! Playing the buzzer, see [`System::set_audio_backend`](crate::System::set_audio_backend).
+//!
+//! The buzzer sounds while the sound timer is non-zero. Backends are told when
+//! it starts and stops, when the XO-CHIP audio pattern changes and when an
+//! emulated frame (1/60 s) has passed.
+
+use std::f32::consts::TAU;
+use std::io::{self, Seek, SeekFrom, Write};
+
+use crate::sound::{AudioPattern, AUDIO_PATTERN_LEN};
+
+/// Frequency of the plain buzzer tone
+const TONE_HZ: f32 = 440.0;
+const FRAMES_PER_SECOND: u64 = 60;
+
+pub trait AudioBackend: Send {
+    /// The buzzer starts (`true`) or stops sounding.
+    fn set_playing(&mut self, playing: bool);
+
+    /// The XO-CHIP audio pattern changed; `None` plays the plain tone.
+    fn set_pattern(&mut self, pattern: Option<AudioPattern>);
+
+    /// An emulated frame has passed. Backends that do not play in real time
+    /// use this as their clock.
+    fn frame(&mut self) {}
+
+    /// Writes out any buffered output, returning the first error that occurred.
+    fn finish(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Plays nothing.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NullAudio;
+
+impl AudioBackend for NullAudio {
+    fn set_playing(&mut self, _playing: bool) {}
+
+    fn set_pattern(&mut self, _pattern: Option<AudioPattern>) {}
+}
+
+/// Generates the buzzer's samples: a sine tone, or the audio pattern if set.
+struct Synth {
+    sample_rate: u32,
+    phase: f32,
+}
+
+impl Synth {
+    fn new(sample_rate: u32) -> Self {
+        Self {
+            sample_rate,
+            phase: 0.0,
+        }
+    }
+
+    fn next_sample(&mut self, pattern: Option<AudioPattern>) -> f32 {
+        let sample_rate = self.sample_rate as f32;
+        match pattern {
+            None => {
+                self.phase = (self.phase + TONE_HZ / sample_rate) % 1.0;
+                (TAU * self.phase).sin()
+            }
+            Some(pattern) => {
+                let bits = (AUDIO_PATTERN_LEN * 8) as f32;
+                self.phase = (self.phase + pattern.playback_rate() / sample_rate) % bits;
+                if pattern.bit(self.phase as usize) {
+                    0.5
+                } else {
+                    -0.5
+                }
+            }
+        }
+    }
+}
+
+#[cfg(feature = "audio-rodio")]
+pub use speaker::RodioAudio;
+
+#[cfg(feature = "audio-rodio")]
+mod speaker {
+    use std::sync::mpsc;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::Duration;
+
+    use rodio::Source;
+
+    use super::{AudioBackend, Synth};
+    use crate::sound::{AudioPattern, SoundError};
+
+    /// Sample rate of the output
+    const SAMPLE_RATE: u32 = 48_000;
+
+    /// Plays the buzzer on the default output device (`audio-rodio` feature).
+    pub struct RodioAudio {
+        sink: rodio::Sink,
+        pattern: Arc<Mutex<Option<AudioPattern>>>,
+        /// dropping it ends the thread keeping the output stream open
+        _stream: mpsc::Sender<()>,
+    }
+
+    impl RodioAudio {
+        pub fn new() -> Result<Self, SoundError> {
+            // The stream stops playing when dropped and cannot be sent to other
+            // threads, so a thread of its own holds it for as long as we live.
+            let (handle_tx, handle_rx) = mpsc::channel();
+            let (stream_tx, stream_rx) = mpsc::channel::<()>();
+            thread::spawn(move || match rodio::OutputStream::try_default() {
+                Ok((_stream, handle)) => {
+                    let _ = handle_tx.send(Ok(handle));
+                    let _ = stream_rx.recv();
+                }
+                Err(err) => {
+                    let _ = handle_tx.send(Err(err.to_string()));
+                }
+            });
+            let handle = handle_rx
+                .recv()
+                .map_err(|err| err.to_string())
+                .and_then(|handle| handle)
+                .map_err(SoundError::SetupError)?;
+            let sink = rodio::Sink::try_new(&handle)
+                .map_err(|err| SoundError::SetupError(err.to_string()))?;
+            let pattern = Arc::new(Mutex::new(None));
+            sink.pause();
+            sink.append(Buzzer {
+                pattern: Arc::clone(&pattern),
+                synth: Synth::new(SAMPLE_RATE),
+            });
+            sink.set_volume(0.9);
+            Ok(Self {
+                sink,
+                pattern,
+                _stream: stream_tx,
+            })
+        }
+    }
+
+    impl AudioBackend for RodioAudio {
+        fn set_playing(&mut self, playing: bool) {
+            if playing {
+                self.sink.play();
+            } else {
+                self.sink.pause();
+            }
+        }
+
+        fn set_pattern(&mut self, pattern: Option<AudioPattern>) {
+            *self.pattern.lock().unwrap() = pattern;
+        }
+    }
+
+    /// Endless source playing either a sine tone or, once set, an audio pattern.
+    struct Buzzer {
+        pattern: Arc<Mutex<Option<AudioPattern>>>,
+        synth: Synth,
+    }
+
+    impl Iterator for Buzzer {
+        type Item = f32;
+
+        fn next(&mut self) -> Option<f32> {
+            let pattern = *self.pattern.lock().unwrap();
+            Some(self.synth.next_sample(pattern))
+        }
+    }
+
+    impl Source for Buzzer {
+        fn current_frame_len(&self) -> Option<usize> {
+            None
+        }
+
+        fn channels(&self) -> u16 {
+            1
+        }
+
+        fn sample_rate(&self) -> u32 {
+            self.synth.sample_rate
+        }
+
+        fn total_duration(&self) -> Option<Duration> {
+            None
+        }
+    }
+}
+
+/// Renders the buzzer to a 16-bit mono WAV file, 1/60 s per emulated frame.
+///
+/// The sizes in the header are filled in by [`AudioBackend::finish`].
+/// Timing is only exact with emulated timers.
+pub struct WavAudio<W: Write + Seek + Send> {
+    out: W,
+    synth: Synth,
+    playing: bool,
+    pattern: Option<AudioPattern>,
+    frames: u64,
+    samples: u64,
+    /// first write error, after which nothing more is written
+    error: Option<io::Error>,
+}
+
+impl<W: Write + Seek + Send> WavAudio<W> {
+    /// Fails if `sample_rate` is 0 or the header cannot be written.
+    pub fn new(mut out: W, sample_rate: u32) -> io::Result<Self> {
+        if sample_rate == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "sample rate must be at least 1 Hz",
+            ));
+        }
+        write_wav_header(&mut out, sample_rate, 0)?;
+        Ok(Self {
+            out,
+            synth: Synth::new(sample_rate),
+            playing: false,
+            pattern: None,
+            frames: 0,
+            samples: 0,
+            error: None,
+        })
+    }
+
+    fn write_frame(&mut self) -> io::Result<()> {
+        self.frames += 1;
+        // whole samples up to the end of the frame, so no drift accumulates
+        let end = self.frames * self.synth.sample_rate as u64 / FRAMES_PER_SECOND;
+        let mut data = Vec::new();
+        for _ in self.samples..end {
+            let sample = if self.playing {
+                self.synth.next_sample(self.pattern)
+            } else {
+                0.0
+            };
+            data.extend_from_slice(&((sample * i16::MAX as f32) as i16).to_le_bytes());
+        }
+        self.samples = end;
+        self.out.write_all(&data)
+    }
+}
+
+impl<W: Write + Seek + Send> AudioBackend for WavAudio<W> {
+    fn set_playing(&mut self, playing: bool) {
+        self.playing = playing;
+    }
+
+    fn set_pattern(&mut self, pattern: Option<AudioPattern>) {
+        self.pattern = pattern;
+    }
+
+    fn frame(&mut self) {
+        if self.error.is_none() {
+            self.error = self.write_frame().err();
+        }
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        if let Some(err) = self.error.take() {
+            return Err(err);
+        }
+        self.out.seek(SeekFrom::Start(0))?;
+        write_wav_header(&mut self.out, self.synth.sample_rate, self.samples * 2)?;
+        self.out.seek(SeekFrom::End(0))?;
+        self.out.flush()
+    }
+}
+
+fn write_wav_header(out: &mut impl Write, sample_rate: u32, data_len: u64) -> io::Result<()> {
+    let too_long = || io::Error::new(io::ErrorKind::InvalidData, "WAV file too long");
+    let data_len = u32::try_from(data_len).map_err(|_| too_long())?;
+    // the RIFF chunk holds the rest of the header too
+    let riff_len = data_len.checked_add(36).ok_or_else(too_long)?;
+    let byte_rate = sample_rate
+        .checked_mul(2)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "sample rate too high"))?;
+    let mut header = Vec::with_capacity(44);
+    header.extend_from_slice(b"RIFF");
+    header.extend_from_slice(&riff_len.to_le_bytes());
+    header.extend_from_slice(b"WAVEfmt ");
+    header.extend_from_slice(&16u32.to_le_bytes());
+    // PCM, mono
+    header.extend_from_slice(&1u16.to_le_bytes());
+    header.extend_from_slice(&1u16.to_le_bytes());
+    header.extend_from_slice(&sample_rate.to_le_bytes());
+    // bytes per second, bytes per sample, bits per sample
+    header.extend_from_slice(&byte_rate.to_le_bytes());
+    header.extend_from_slice(&2u16.to_le_bytes());
+    header.extend_from_slice(&16u16.to_le_bytes());
+    header.extend_from_slice(b"data");
+    header.extend_from_slice(&data_len.to_le_bytes());
+    out.write_all(&header)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    #[test]
+    fn wav_sizes_are_filled_in() {
+        let mut wav = WavAudio::new(Cursor::new(Vec::new()), 6000).unwrap();
+        wav.set_playing(true);
+        for _ in 0..3 {
+            wav.frame();
+        }
+        wav.finish().unwrap();
+        let bytes = wav.out.into_inner();
+        let field = |at: usize| u32::from_le_bytes(bytes[at..(at + 4)].try_into().unwrap());
+        // 100 samples per frame
+        assert_eq!(bytes.len(), 44 + 600);
+        assert_eq!(field(4), 36 + 600);
+        assert_eq!(field(28), 12000);
+        assert_eq!(field(40), 600);
+    }
+
+    #[test]
+    fn oversized_headers_are_rejected() {
+        let mut out = Vec::new();
+        write_wav_header(&mut out, 44_100, (u32::MAX - 36) as u64).unwrap();
+        for data_len in [(u32::MAX - 35) as u64, u64::MAX] {
+            let err = write_wav_header(&mut out, 44_100, data_len).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        }
+        let err = write_wav_header(&mut out, u32::MAX, 0).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn zero_sample_rate_is_rejected() {
+        let Err(err) = WavAudio::new(Cursor::new(Vec::new()), 0) else {
+            panic!("WAV audio at 0 Hz");
+        };
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+}
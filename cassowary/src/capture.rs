@@ -0,0 +1,324 @@
+//! Screenshots (PNG) and recordings (animated GIF) of the display.
+
+use std::borrow::Cow;
+use std::io::{self, Write};
+use std::str::FromStr;
+
+use thiserror::Error;
+
+use crate::display::Display;
+use crate::render::{Frame, Renderer};
+
+/// Default number of image pixels per display pixel
+pub const DEFAULT_SCALE: usize = 8;
+
+#[derive(Error, Debug)]
+pub enum PaletteError {
+    #[error("invalid color {0:?} (expected RRGGBB in hexadecimal)")]
+    InvalidColor(String),
+    #[error("a palette has 2 or 4 colors, got {0}")]
+    WrongCount(usize),
+}
+
+/// Colors of the pixel values: off, plane 1, plane 2 and both planes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Palette(pub [[u8; 3]; 4]);
+
+impl Default for Palette {
+    fn default() -> Self {
+        Self([[0x00; 3], [0xFF; 3], [0xAA; 3], [0x55; 3]])
+    }
+}
+
+/// Parses comma separated `RRGGBB` colors, e.g. `000000,ffffff`. With only
+/// two colors, the XO-CHIP plane colors stay the default ones.
+impl FromStr for Palette {
+    type Err = PaletteError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let colors = s
+            .split(',')
+            .map(|color| {
+                let hex = color.len() == 6 && color.bytes().all(|b| b.is_ascii_hexdigit());
+                let rgb = u32::from_str_radix(color, 16)
+                    .ok()
+                    .filter(|_| hex)
+                    .ok_or_else(|| PaletteError::InvalidColor(color.to_string()))?;
+                let [_, r, g, b] = rgb.to_be_bytes();
+                Ok([r, g, b])
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if colors.len() != 2 && colors.len() != 4 {
+            return Err(PaletteError::WrongCount(colors.len()));
+        }
+        let mut palette = Self::default();
+        palette.0[..colors.len()].copy_from_slice(&colors);
+        Ok(palette)
+    }
+}
+
+impl Palette {
+    fn bytes(&self) -> Vec<u8> {
+        self.0.concat()
+    }
+}
+
+/// The frame's pixel values stretched to `width x height`.
+fn scaled(frame: &Frame, width: usize, height: usize) -> Vec<u8> {
+    let mut pixels = Vec::with_capacity(width * height);
+    for y in 0..height {
+        for x in 0..width {
+            let pixel = frame.pixel(x * frame.width / width, y * frame.height / height);
+            pixels.push(pixel & 0x03);
+        }
+    }
+    pixels
+}
+
+/// Writes the frame as a PNG image, each pixel `scale x scale` big.
+pub fn write_png(
+    out: impl Write,
+    frame: &Frame,
+    palette: &Palette,
+    scale: usize,
+) -> io::Result<()> {
+    let (width, height) = (frame.width * scale, frame.height * scale);
+    let mut encoder = png::Encoder::new(out, width as u32, height as u32);
+    encoder.set_color(png::ColorType::Indexed);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_palette(palette.bytes());
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&scaled(frame, width, height))?;
+    writer.finish()?;
+    Ok(())
+}
+
+/// Records the presented frames as an animated GIF playing at 60 frames per
+/// second, until [`Renderer::finish`] is called.
+///
+/// The image has the size of the first frame; frames in another resolution
+/// are stretched to it. Frames that repeat the previous one only extend
+/// its duration.
+pub struct GifRecorder {
+    out: Option<Box<dyn Write + Send>>,
+    encoder: Option<gif::Encoder<Box<dyn Write + Send>>>,
+    palette: Palette,
+    scale: usize,
+    /// image size, set by the first frame
+    size: Option<(usize, usize)>,
+    limit: Option<u64>,
+    /// frames presented so far
+    frames: u64,
+    /// the image not written yet and the frame it was first shown in
+    pending: Option<(Vec<u8>, u64)>,
+    /// first write error, after which nothing more is written
+    error: Option<io::Error>,
+}
+
+impl GifRecorder {
+    pub fn new(out: impl Write + Send + 'static, palette: Palette, scale: usize) -> Self {
+        Self {
+            out: Some(Box::new(out)),
+            encoder: None,
+            palette,
+            scale,
+            size: None,
+            limit: None,
+            frames: 0,
+            pending: None,
+            error: None,
+        }
+    }
+
+    /// Only records the first `frames` frames.
+    pub fn with_limit(mut self, frames: u64) -> Self {
+        self.limit = Some(frames);
+        self
+    }
+
+    /// Writes the pending image, shown from its frame up to frame `end`.
+    fn write_pending(&mut self, end: u64) -> io::Result<()> {
+        let Some((pixels, start)) = self.pending.take() else {
+            return Ok(());
+        };
+        let (width, height) = self.size.unwrap();
+        if self.encoder.is_none() {
+            let out = self.out.take().unwrap();
+            let mut encoder =
+                gif::Encoder::new(out, width as u16, height as u16, &self.palette.bytes())
+                    .map_err(io::Error::other)?;
+            encoder
+                .set_repeat(gif::Repeat::Infinite)
+                .map_err(io::Error::other)?;
+            self.encoder = Some(encoder);
+        }
+        // GIF delays are in 1/100 s: round the frame times rather than the
+        // durations, so that they add up to 60 frames per second
+        let centis = |frame: u64| (frame * 100 + 30) / 60;
+        let frame = gif::Frame {
+            width: width as u16,
+            height: height as u16,
+            buffer: Cow::Owned(pixels),
+            delay: (centis(end) - centis(start)).min(u16::MAX as u64) as u16,
+            ..gif::Frame::default()
+        };
+        self.encoder
+            .as_mut()
+            .unwrap()
+            .write_frame(&frame)
+            .map_err(io::Error::other)
+    }
+}
+
+impl Renderer for GifRecorder {
+    fn present(&mut self, display: &Display, changed: bool) {
+        if self.error.is_some() || self.limit.is_some_and(|limit| self.frames >= limit) {
+            return;
+        }
+        if changed || self.pending.is_none() {
+            let frame = Frame::capture(display);
+            let (width, height) = *self
+                .size
+                .get_or_insert((frame.width * self.scale, frame.height * self.scale));
+            let pixels = scaled(&frame, width, height);
+            if self.pending.as_ref().map(|(pending, _)| pending) != Some(&pixels) {
+                if let Err(err) = self.write_pending(self.frames) {
+                    self.error = Some(err);
+                }
+                self.pending = Some((pixels, self.frames));
+            }
+        }
+        self.frames += 1;
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        if let Some(err) = self.error.take() {
+            return Err(err);
+        }
+        self.write_pending(self.frames)?;
+        match self.encoder.take() {
+            Some(encoder) => encoder.into_inner()?.flush(),
+            // nothing was recorded
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::tests::assembled;
+    use crate::System;
+
+    /// A system showing the font's `digit` at (1, 2).
+    fn digit(digit: u8) -> System {
+        let mut system = assembled(&format!(
+            "LD V0 1\nLD V1 2\nLD V2 {}\nLDSPR V2\nDRW V0 V1 5\nHALT",
+            digit
+        ));
+        system.run().unwrap();
+        system
+    }
+
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn palettes() {
+        let two: Palette = "102030,A0B0C0".parse().unwrap();
+        let default = Palette::default();
+        assert_eq!(two.0[..2], [[0x10, 0x20, 0x30], [0xA0, 0xB0, 0xC0]]);
+        assert_eq!(two.0[2..], default.0[2..]);
+        let four: Palette = "000000,ffffff,ff0000,00ff00".parse().unwrap();
+        assert_eq!(four.0[3], [0x00, 0xFF, 0x00]);
+
+        for (palette, count) in [("000000", 1), ("000000,ffffff,ff0000", 3)] {
+            assert!(matches!(
+                palette.parse::<Palette>(),
+                Err(PaletteError::WrongCount(n)) if n == count
+            ));
+        }
+        for color in ["fffff", "fffffff", "gggggg", "+fffff", ""] {
+            assert!(matches!(
+                format!("000000,{}", color).parse::<Palette>(),
+                Err(PaletteError::InvalidColor(invalid)) if invalid == color
+            ));
+        }
+    }
+
+    #[test]
+    fn png_round_trip() {
+        let frame = Frame::capture(digit(0).display());
+        let palette: Palette = "102030,a0b0c0".parse().unwrap();
+        let mut png = Vec::new();
+        write_png(&mut png, &frame, &palette, 2).unwrap();
+
+        let mut reader = png::Decoder::new(png.as_slice()).read_info().unwrap();
+        let info = reader.info();
+        assert_eq!((info.width, info.height), (128, 64));
+        assert_eq!(info.palette.as_deref(), Some(&palette.bytes()[..]));
+        let mut pixels = vec![0; reader.output_buffer_size()];
+        reader.next_frame(&mut pixels).unwrap();
+        assert_eq!(pixels, scaled(&frame, 128, 64));
+        // the top left pixel of the `0`, 2 x 2 image pixels big
+        assert_eq!(pixels[4 * 128 + 2..][..3], [1, 1, 1]);
+        assert_eq!(pixels[3 * 128 + 2], 0);
+    }
+
+    /// The delay of each frame of a GIF, in 1/100 s.
+    fn gif_delays(gif: &[u8]) -> Vec<u16> {
+        let mut decoder = gif::Decoder::new(gif).unwrap();
+        let mut delays = Vec::new();
+        while let Some(frame) = decoder.read_next_frame().unwrap() {
+            delays.push(frame.delay);
+        }
+        delays
+    }
+
+    #[test]
+    fn repeated_frames_extend_the_previous_one() {
+        let (zero, one) = (digit(0), digit(1));
+        let out = Shared::default();
+        let mut gif = GifRecorder::new(out.clone(), Palette::default(), 1);
+        gif.present(zero.display(), true);
+        gif.present(zero.display(), false);
+        // changed, but back to the same pixels
+        gif.present(zero.display(), true);
+        gif.present(one.display(), true);
+        gif.finish().unwrap();
+        // frames 0 to 3 and 3 to 4, at 60 per second
+        assert_eq!(gif_delays(&out.0.lock().unwrap()), [5, 2]);
+    }
+
+    #[test]
+    fn recording_stops_at_the_limit() {
+        let (zero, one) = (digit(0), digit(1));
+        let out = Shared::default();
+        let mut gif = GifRecorder::new(out.clone(), Palette::default(), 1).with_limit(2);
+        for system in [&zero, &zero, &one, &zero] {
+            gif.present(system.display(), true);
+        }
+        gif.finish().unwrap();
+        assert_eq!(gif_delays(&out.0.lock().unwrap()), [3]);
+    }
+
+    #[test]
+    fn nothing_recorded_writes_nothing() {
+        let out = Shared::default();
+        let mut gif = GifRecorder::new(out.clone(), Palette::default(), 1);
+        gif.finish().unwrap();
+        assert!(out.0.lock().unwrap().is_empty());
+    }
+}
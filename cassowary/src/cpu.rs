@@ -2,16 +2,19 @@ use crate::display::Display;
 use crate::instructions::{Instruction, MemAddr, RegId};
 use crate::keyboard::KeyBoard;
 use crate::memory::{Memory, MemoryError};
-use crate::sound::SoundSystem;
+use crate::quirks::{IndexIncrement, Quirks};
+use crate::random::{RandomSource, SeededRandom};
+use crate::sound::{SoundSystem, AUDIO_PATTERN_LEN};
+use crate::state::CpuState;
 use crate::timer::DelayTimer;
 
-use rand::{self, Rng};
 use thiserror::Error;
 
-const TRACE: bool = false;
 const COND_REG: RegId = 0xF;
 const HEX_SPRITE_BASE: MemAddr = 0x0100;
 const HEX_SPRITE_HEIGHT: MemAddr = 5;
+const BIG_HEX_SPRITE_BASE: MemAddr = 0x0150;
+const BIG_HEX_SPRITE_HEIGHT: MemAddr = 10;
 
 #[derive(Error, Debug)]
 pub enum CpuError {
@@ -29,12 +32,39 @@ pub enum CpuError {
     Halt,
 }
 
+/// What happened while executing a single instruction.
+#[derive(Debug, Clone, Copy)]
+pub struct Step {
+    pub opcode: u16,
+    pub instruction: Instruction,
+    pub pc_before: MemAddr,
+    pub pc_after: MemAddr,
+    /// `true` if the instruction changed any pixel on the display
+    pub display_changed: bool,
+    /// `true` if the CPU is blocked on `LDK VX` until a key is pressed
+    pub awaiting_key: bool,
+    /// `true` if the CPU has halted (it will not execute further instructions)
+    pub halted: bool,
+}
+
 pub struct Cpu {
     registers: [u8; 16],
     pc: MemAddr,
     sp: usize,
     index: MemAddr,
     stack: [MemAddr; 16],
+    /// SUPER-CHIP RPL user flags
+    flags: [u8; 16],
+    awaiting_key: bool,
+    halted: bool,
+    rng: Box<dyn RandomSource>,
+    quirks: Quirks,
+}
+
+impl Default for Cpu {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Cpu {
@@ -45,9 +75,51 @@ impl Cpu {
             sp: 0,
             index: 0,
             stack: [0; 16],
+            flags: [0; 16],
+            awaiting_key: false,
+            halted: false,
+            rng: Box::new(SeededRandom::default()),
+            quirks: Quirks::default(),
         }
     }
 
+    pub fn quirks(&self) -> &Quirks {
+        &self.quirks
+    }
+
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+    }
+
+    pub fn set_random_source(&mut self, rng: Box<dyn RandomSource>) {
+        self.rng = rng;
+    }
+
+    pub fn pc(&self) -> MemAddr {
+        self.pc
+    }
+
+    pub fn index(&self) -> MemAddr {
+        self.index
+    }
+
+    /// Return addresses of the active subroutine calls, outermost first.
+    pub fn stack(&self) -> &[MemAddr] {
+        &self.stack[..self.sp]
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    pub fn is_awaiting_key(&self) -> bool {
+        self.awaiting_key
+    }
+
+    pub fn registers(&self) -> &[u8; 16] {
+        &self.registers
+    }
+
     pub fn get_register(&self, idx: usize) -> u8 {
         self.registers[idx]
     }
@@ -56,6 +128,38 @@ impl Cpu {
         self.registers[idx] = value;
     }
 
+    pub(crate) fn state(&self) -> CpuState {
+        CpuState {
+            registers: self.registers,
+            pc: self.pc,
+            index: self.index,
+            sp: self.sp,
+            stack: self.stack,
+            flags: self.flags,
+            awaiting_key: self.awaiting_key,
+            halted: self.halted,
+            rng: self.rng.state(),
+        }
+    }
+
+    /// Restores a saved state. A saved random generator state that the current
+    /// source cannot restore replaces it with a [`SeededRandom`].
+    pub(crate) fn restore(&mut self, state: &CpuState) {
+        self.registers = state.registers;
+        self.pc = state.pc;
+        self.index = state.index;
+        self.sp = state.sp;
+        self.stack = state.stack;
+        self.flags = state.flags;
+        self.awaiting_key = state.awaiting_key;
+        self.halted = state.halted;
+        if let Some(rng) = state.rng {
+            if !self.rng.restore(rng) {
+                self.rng = Box::new(SeededRandom::from_state(rng));
+            }
+        }
+    }
+
     pub fn dump(&self) {
         println!("PC: {:03X}    I: {:03X}", self.pc, self.index);
         println!("Regs: ");
@@ -101,25 +205,86 @@ impl Cpu {
         sound_timer: &mut SoundSystem,
     ) -> Result<(), CpuError> {
         loop {
-            let instr = Instruction::decode(self.fetch(mem)?);
-            if TRACE {
-                println!("TRACE: {:?}", instr);
+            let step = self.step(mem, delay, display, keyboard, sound_timer)?;
+            if step.halted {
+                return Ok(());
             }
-            match self.execute(instr, mem, delay, display, keyboard, sound_timer) {
-                Err(CpuError::Halt) => return Ok(()),
-                Err(err) => return Err(err),
-                _ => {
-                    if TRACE {
-                        self.dump();
-                    }
-                    continue;
+        }
+    }
+
+    /// Fetches and executes a single instruction.
+    ///
+    /// A halted CPU does not execute anything; the returned step repeats
+    /// the halting instruction with `halted` set. On error the PC is left
+    /// on the faulting instruction and nothing else has changed, as
+    /// instructions check the memory they access before changing anything.
+    pub fn step(
+        &mut self,
+        mem: &mut Memory,
+        delay: &mut DelayTimer,
+        display: &mut Display,
+        keyboard: &mut KeyBoard,
+        sound_timer: &mut SoundSystem,
+    ) -> Result<Step, CpuError> {
+        let pc_before = self.pc;
+        if self.halted {
+            return Ok(Step {
+                opcode: 0x0000,
+                instruction: Instruction::Halt,
+                pc_before,
+                pc_after: pc_before,
+                display_changed: false,
+                awaiting_key: false,
+                halted: true,
+            });
+        }
+
+        mem.set_pc(pc_before);
+        let (opcode, instr) =
+            match self.fetch_and_execute(mem, delay, display, keyboard, sound_timer) {
+                Ok(executed) => executed,
+                Err(err) => {
+                    // leave the PC on the faulting instruction
+                    self.pc = pc_before;
+                    return Err(err);
                 }
-            }
+            };
+        Ok(Step {
+            opcode,
+            instruction: instr,
+            pc_before,
+            pc_after: self.pc,
+            display_changed: display.take_changed(),
+            awaiting_key: self.awaiting_key,
+            halted: self.halted,
+        })
+    }
+
+    fn fetch_and_execute(
+        &mut self,
+        mem: &mut Memory,
+        delay: &mut DelayTimer,
+        display: &mut Display,
+        keyboard: &mut KeyBoard,
+        sound_timer: &mut SoundSystem,
+    ) -> Result<(u16, Instruction), CpuError> {
+        let opcode = self.fetch(mem)?;
+        let instr = if self.quirks.long_index_load && Instruction::is_long(opcode) {
+            let operand = self.fetch(mem)?;
+            Instruction::decode_long(opcode, operand)
+        } else {
+            Instruction::decode(opcode)
+        };
+        match self.execute(instr, mem, delay, display, keyboard, sound_timer) {
+            Err(CpuError::Halt) => self.halted = true,
+            Err(err) => return Err(err),
+            Ok(()) => {}
         }
+        Ok((opcode, instr))
     }
 
     fn fetch(&mut self, mem: &Memory) -> Result<u16, CpuError> {
-        let opcode = mem.load_u16(self.pc)?;
+        let opcode = mem.fetch_u16(self.pc)?;
         self.inc_pc()?;
         Ok(opcode)
     }
@@ -142,40 +307,54 @@ impl Cpu {
             Instruction::XorXY(x, y) => self.xor_xy(x, y),
             Instruction::AddXY(x, y) => self.add_xy(x, y),
             Instruction::SubXY(x, y) => self.sub_xy(x, y),
-            Instruction::Shr1X(x) => self.shr1_x(x),
+            Instruction::Shr1X(x, y) => self.shr1_x(x, y),
             Instruction::SubYX(x, y) => self.sub_yx(x, y),
-            Instruction::Shl1X(x) => self.shl1_x(x),
-            Instruction::SkipIfEqX(x, imm) => self.skip_if_eq_x(x, imm),
-            Instruction::SkipIfNeX(x, imm) => self.skip_if_ne_x(x, imm),
-            Instruction::SkipIfEqXY(x, y) => self.skip_if_eq_xy(x, y),
-            Instruction::SkipIfNeXY(x, y) => self.skip_if_ne_xy(x, y),
+            Instruction::Shl1X(x, y) => self.shl1_x(x, y),
+            Instruction::SkipIfEqX(x, imm) => self.skip_if_eq_x(x, imm, mem),
+            Instruction::SkipIfNeX(x, imm) => self.skip_if_ne_x(x, imm, mem),
+            Instruction::SkipIfEqXY(x, y) => self.skip_if_eq_xy(x, y, mem),
+            Instruction::SkipIfNeXY(x, y) => self.skip_if_ne_xy(x, y, mem),
             Instruction::Jump(addr) => self.jump(addr),
             Instruction::JumpV0(addr) => self.jump_v0(addr),
             Instruction::Call(addr) => self.call(addr),
-            Instruction::SkipIfKeyEqX(x) => self.skip_if_key_eq_x(x, keyboard),
-            Instruction::SkipIfKeyNeX(x) => self.skip_if_key_ne_x(x, keyboard),
+            Instruction::SkipIfKeyEqX(x) => self.skip_if_key_eq_x(x, keyboard, mem),
+            Instruction::SkipIfKeyNeX(x) => self.skip_if_key_ne_x(x, keyboard, mem),
             Instruction::GetDelayX(x) => self.get_delay_x(x, delay),
             Instruction::SetDelayX(x) => self.set_delay_x(x, delay),
             Instruction::SetSoundX(x) => self.set_sound_x(x, sound_timer),
             Instruction::AwaitKeyX(x) => self.await_key_x(x, keyboard),
             Instruction::RandX(x, imm) => self.rand_x(x, imm),
-            Instruction::AddIX(x) => self.add_i_x(x),
-            Instruction::SetI(addr) => self.set_i(addr),
+            Instruction::AddIX(x) => self.add_i_x(x, mem),
+            Instruction::SetI(addr) | Instruction::SetILong(addr) => self.set_i(addr),
             Instruction::SpriteAddrIX(x) => self.sprite_addr_i_x(x),
             Instruction::DumpBcdIX(x) => self.dump_bcd_i_x(x, mem),
             Instruction::RegDumpIX(x) => self.reg_dump_i_x(x, mem),
             Instruction::RegLoadIX(x) => self.reg_load_i_x(x, mem),
+            Instruction::RegDumpRangeIXY(x, y) => self.reg_dump_range_i_xy(x, y, mem),
+            Instruction::RegLoadRangeIXY(x, y) => self.reg_load_range_i_xy(x, y, mem),
+            Instruction::LoadAudio => self.load_audio(mem, sound_timer),
+            Instruction::SetPitchX(x) => self.set_pitch_x(x, sound_timer),
             Instruction::Ret => self.ret(),
-            Instruction::Halt => Err(CpuError::Halt),
+            Instruction::BigSpriteAddrIX(x) => self.big_sprite_addr_i_x(x),
+            Instruction::StoreFlagsX(x) => self.store_flags_x(x),
+            Instruction::LoadFlagsX(x) => self.load_flags_x(x),
+            Instruction::Halt | Instruction::Exit => Err(CpuError::Halt),
             Instruction::DispClear => self.display_clear(display),
             Instruction::DispDraw(x, y, imm) => self.display_draw(x, y, imm, display, mem),
+            Instruction::ScrollDown(n) => self.display_scroll_down(n, display),
+            Instruction::ScrollUp(n) => self.display_scroll_up(n, display),
+            Instruction::ScrollRight => self.display_scroll_right(display),
+            Instruction::ScrollLeft => self.display_scroll_left(display),
+            Instruction::LoRes => self.display_set_hires(false, display),
+            Instruction::HiRes => self.display_set_hires(true, display),
+            Instruction::SelectPlanes(planes) => self.display_select_planes(planes, display),
             Instruction::NoOp(_) => Ok(()),
             Instruction::Unsupported(opcode) => Err(CpuError::IllegalInstruction(opcode)),
         }
     }
 
     fn push_stack(&mut self, addr: MemAddr) -> Result<(), CpuError> {
-        if self.sp > self.stack.len() {
+        if self.sp >= self.stack.len() {
             return Err(CpuError::StackOverflow);
         }
 
@@ -185,7 +364,7 @@ impl Cpu {
     }
 
     fn pop_stack(&mut self) -> Result<MemAddr, CpuError> {
-        if self.sp <= 0 {
+        if self.sp == 0 {
             return Err(CpuError::StackUnderflow);
         }
 
@@ -197,11 +376,37 @@ impl Cpu {
         self.registers[COND_REG] = condition;
     }
 
+    fn shift_source(&self, x: RegId, y: RegId) -> RegId {
+        if self.quirks.shift_uses_vy {
+            y
+        } else {
+            x
+        }
+    }
+
+    fn reset_condition_after_logic(&mut self) {
+        if self.quirks.logic_resets_vf {
+            self.set_condition(0);
+        }
+    }
+
+    fn increment_index_after_load_store(&mut self, x: RegId) {
+        match self.quirks.load_store_index {
+            IndexIncrement::Unchanged => {}
+            IndexIncrement::ByX => self.index += x,
+            IndexIncrement::ByXPlusOne => self.index += x + 1,
+        }
+    }
+
     fn rand_byte(&mut self) -> u8 {
-        rand::thread_rng().gen()
+        self.rng.next_byte()
     }
 
-    fn skip_instruction(&mut self) -> Result<(), CpuError> {
+    /// Skips the next instruction, which is two words long for `LDL I NNNN`.
+    fn skip_instruction(&mut self, mem: &Memory) -> Result<(), CpuError> {
+        if self.quirks.long_index_load && Instruction::is_long(mem.load_u16(self.pc)?) {
+            self.inc_pc()?;
+        }
         self.inc_pc()
     }
 
@@ -209,6 +414,15 @@ impl Cpu {
         self.pc = mem_addr_add(self.pc, 2)?;
         Ok(())
     }
+
+    /// Checks that the `len` bytes from `I` are in memory, so that an
+    /// instruction accessing them fails before changing anything.
+    fn check_index_range(&self, len: usize, mem: &Memory) -> Result<(), CpuError> {
+        if mem_addr_add(self.index, len)? > mem.size() {
+            return Err(MemoryError::OutOfBounds.into());
+        }
+        Ok(())
+    }
 }
 
 // instructions
@@ -224,8 +438,13 @@ impl Cpu {
     }
 
     fn jump_v0(&mut self, offset: MemAddr) -> Result<(), CpuError> {
-        let v0 = self.registers[0];
-        self.pc = mem_addr_add(v0 as MemAddr, offset)?;
+        let reg = if self.quirks.jump_uses_vx {
+            (offset >> 8) & 0xF
+        } else {
+            0
+        };
+        let base = self.registers[reg];
+        self.pc = mem_addr_add(base as MemAddr, offset)?;
         Ok(())
     }
 
@@ -239,6 +458,36 @@ impl Cpu {
         Ok(())
     }
 
+    fn display_scroll_down(&mut self, n: u8, display: &mut Display) -> Result<(), CpuError> {
+        display.scroll_down(n as usize);
+        Ok(())
+    }
+
+    fn display_scroll_up(&mut self, n: u8, display: &mut Display) -> Result<(), CpuError> {
+        display.scroll_up(n as usize);
+        Ok(())
+    }
+
+    fn display_scroll_right(&mut self, display: &mut Display) -> Result<(), CpuError> {
+        display.scroll_right();
+        Ok(())
+    }
+
+    fn display_scroll_left(&mut self, display: &mut Display) -> Result<(), CpuError> {
+        display.scroll_left();
+        Ok(())
+    }
+
+    fn display_set_hires(&mut self, hires: bool, display: &mut Display) -> Result<(), CpuError> {
+        display.set_hires(hires);
+        Ok(())
+    }
+
+    fn display_select_planes(&mut self, planes: u8, display: &mut Display) -> Result<(), CpuError> {
+        display.select_planes(planes);
+        Ok(())
+    }
+
     fn add_xy(&mut self, x: RegId, y: RegId) -> Result<(), CpuError> {
         let xv = self.registers[x];
         let yv = self.registers[y];
@@ -270,6 +519,7 @@ impl Cpu {
         let xv = self.registers[x];
         let yv = self.registers[y];
         self.registers[x] = xv & yv;
+        self.reset_condition_after_logic();
         Ok(())
     }
 
@@ -277,6 +527,7 @@ impl Cpu {
         let xv = self.registers[x];
         let yv = self.registers[y];
         self.registers[x] = xv | yv;
+        self.reset_condition_after_logic();
         Ok(())
     }
 
@@ -284,6 +535,7 @@ impl Cpu {
         let xv = self.registers[x];
         let yv = self.registers[y];
         self.registers[x] = xv ^ yv;
+        self.reset_condition_after_logic();
         Ok(())
     }
 
@@ -292,16 +544,16 @@ impl Cpu {
         Ok(())
     }
 
-    fn shr1_x(&mut self, x: RegId) -> Result<(), CpuError> {
-        let xv = self.registers[x];
+    fn shr1_x(&mut self, x: RegId, y: RegId) -> Result<(), CpuError> {
+        let xv = self.registers[self.shift_source(x, y)];
         let lsb = xv & 0x01;
         self.registers[x] = xv >> 1;
         self.set_condition(lsb);
         Ok(())
     }
 
-    fn shl1_x(&mut self, x: RegId) -> Result<(), CpuError> {
-        let xv = self.registers[x];
+    fn shl1_x(&mut self, x: RegId, y: RegId) -> Result<(), CpuError> {
+        let xv = self.registers[self.shift_source(x, y)];
         let msb = (xv & 0x80) >> 7;
         self.registers[x] = xv << 1;
         self.set_condition(msb);
@@ -319,60 +571,81 @@ impl Cpu {
         Ok(())
     }
 
-    fn skip_if_ne_x(&mut self, x: RegId, imm: u8) -> Result<(), CpuError> {
+    fn skip_if_ne_x(&mut self, x: RegId, imm: u8, mem: &Memory) -> Result<(), CpuError> {
         let xv = self.registers[x];
         if xv != imm {
-            self.skip_instruction()?;
+            self.skip_instruction(mem)?;
         }
         Ok(())
     }
 
-    fn skip_if_eq_x(&mut self, x: RegId, imm: u8) -> Result<(), CpuError> {
+    fn skip_if_eq_x(&mut self, x: RegId, imm: u8, mem: &Memory) -> Result<(), CpuError> {
         let xv = self.registers[x];
         if xv == imm {
-            self.skip_instruction()?;
+            self.skip_instruction(mem)?;
         }
         Ok(())
     }
 
-    fn skip_if_eq_xy(&mut self, x: RegId, y: RegId) -> Result<(), CpuError> {
+    fn skip_if_eq_xy(&mut self, x: RegId, y: RegId, mem: &Memory) -> Result<(), CpuError> {
         let xv = self.registers[x];
         let yv = self.registers[y];
         if xv == yv {
-            self.skip_instruction()?;
+            self.skip_instruction(mem)?;
         }
         Ok(())
     }
 
-    fn skip_if_ne_xy(&mut self, x: RegId, y: RegId) -> Result<(), CpuError> {
+    fn skip_if_ne_xy(&mut self, x: RegId, y: RegId, mem: &Memory) -> Result<(), CpuError> {
         let xv = self.registers[x];
         let yv = self.registers[y];
         if xv != yv {
-            self.skip_instruction()?;
+            self.skip_instruction(mem)?;
         }
         Ok(())
     }
 
-    fn skip_if_key_eq_x(&mut self, x: RegId, keyboard: &mut KeyBoard) -> Result<(), CpuError> {
-        let xv = self.registers[x];
-        let key = keyboard.get_key_pressed();
-        if xv == key {
-            self.skip_instruction()?;
+    fn skip_if_key_eq_x(
+        &mut self,
+        x: RegId,
+        keyboard: &KeyBoard,
+        mem: &Memory,
+    ) -> Result<(), CpuError> {
+        if keyboard.is_down(self.registers[x]) {
+            self.skip_instruction(mem)?;
         }
         Ok(())
     }
 
-    fn skip_if_key_ne_x(&mut self, x: RegId, keyboard: &mut KeyBoard) -> Result<(), CpuError> {
-        let xv = self.registers[x];
-        let key = keyboard.get_key_pressed();
-        if xv != key {
-            self.skip_instruction()?;
+    fn skip_if_key_ne_x(
+        &mut self,
+        x: RegId,
+        keyboard: &KeyBoard,
+        mem: &Memory,
+    ) -> Result<(), CpuError> {
+        if !keyboard.is_down(self.registers[x]) {
+            self.skip_instruction(mem)?;
         }
         Ok(())
     }
 
     fn await_key_x(&mut self, x: RegId, keyboard: &mut KeyBoard) -> Result<(), CpuError> {
-        self.registers[x] = keyboard.await_key_press();
+        let on_press = self.quirks.key_wait_on_press;
+        if !self.awaiting_key {
+            keyboard.start_wait(on_press);
+        }
+        match keyboard.take_key(on_press) {
+            Some(key) => {
+                self.registers[x] = key;
+                self.awaiting_key = false;
+            }
+            None => {
+                // re-execute this instruction on the next step, while the
+                // timers keep running and keys are delivered in between
+                self.pc -= 2;
+                self.awaiting_key = true;
+            }
+        }
         Ok(())
     }
 
@@ -396,8 +669,13 @@ impl Cpu {
         Ok(())
     }
 
-    fn add_i_x(&mut self, x: RegId) -> Result<(), CpuError> {
-        self.index += self.registers[x] as MemAddr;
+    /// `I` wraps around at the end of memory.
+    fn add_i_x(&mut self, x: RegId, mem: &Memory) -> Result<(), CpuError> {
+        let index = self.index + self.registers[x] as MemAddr;
+        if self.quirks.index_overflow_sets_vf {
+            self.set_condition(if index >= mem.size() { 1 } else { 0 });
+        }
+        self.index = index % mem.size();
         Ok(())
     }
 
@@ -407,10 +685,27 @@ impl Cpu {
         Ok(())
     }
 
+    fn big_sprite_addr_i_x(&mut self, x: RegId) -> Result<(), CpuError> {
+        let xv = self.registers[x] & 0x0F;
+        self.index = BIG_HEX_SPRITE_BASE + (xv as MemAddr) * BIG_HEX_SPRITE_HEIGHT;
+        Ok(())
+    }
+
+    fn store_flags_x(&mut self, x: RegId) -> Result<(), CpuError> {
+        self.flags[..=x].copy_from_slice(&self.registers[..=x]);
+        Ok(())
+    }
+
+    fn load_flags_x(&mut self, x: RegId) -> Result<(), CpuError> {
+        self.registers[..=x].copy_from_slice(&self.flags[..=x]);
+        Ok(())
+    }
+
     fn dump_bcd_i_x(&mut self, x: RegId, mem: &mut Memory) -> Result<(), CpuError> {
+        self.check_index_range(3, mem)?;
         let base = self.index;
         let bcdx = format!("{:03}", self.registers[x]);
-        let bcds = bcdx.chars().map(|c| char_to_bcd(c));
+        let bcds = bcdx.chars().map(char_to_bcd);
         for (offset, d) in bcds.into_iter().enumerate() {
             let addr = mem_addr_add(base, offset)?;
             mem.store_byte(addr, d)?;
@@ -419,19 +714,61 @@ impl Cpu {
     }
 
     fn reg_dump_i_x(&mut self, x: RegId, mem: &mut Memory) -> Result<(), CpuError> {
+        self.check_index_range(x + 1, mem)?;
         let base = self.index;
         for (offset, r) in (0..=x).enumerate() {
             let addr = mem_addr_add(base, offset)?;
             mem.store_byte(addr, self.registers[r])?;
         }
+        self.increment_index_after_load_store(x);
         Ok(())
     }
 
     fn reg_load_i_x(&mut self, x: RegId, mem: &mut Memory) -> Result<(), CpuError> {
+        self.check_index_range(x + 1, mem)?;
         let base = self.index;
         for (offset, r) in (0..=x).enumerate() {
-            self.registers[r] = mem.load_byte(base + offset)?;
+            let addr = mem_addr_add(base, offset)?;
+            self.registers[r] = mem.load_byte(addr)?;
         }
+        self.increment_index_after_load_store(x);
+        Ok(())
+    }
+
+    fn reg_dump_range_i_xy(
+        &mut self,
+        x: RegId,
+        y: RegId,
+        mem: &mut Memory,
+    ) -> Result<(), CpuError> {
+        self.check_index_range(x.abs_diff(y) + 1, mem)?;
+        for (offset, r) in reg_range(x, y).enumerate() {
+            let addr = mem_addr_add(self.index, offset)?;
+            mem.store_byte(addr, self.registers[r])?;
+        }
+        Ok(())
+    }
+
+    fn reg_load_range_i_xy(&mut self, x: RegId, y: RegId, mem: &Memory) -> Result<(), CpuError> {
+        self.check_index_range(x.abs_diff(y) + 1, mem)?;
+        for (offset, r) in reg_range(x, y).enumerate() {
+            let addr = mem_addr_add(self.index, offset)?;
+            self.registers[r] = mem.load_byte(addr)?;
+        }
+        Ok(())
+    }
+
+    fn load_audio(&mut self, mem: &Memory, sound_timer: &mut SoundSystem) -> Result<(), CpuError> {
+        let mut pattern = [0; AUDIO_PATTERN_LEN];
+        for (offset, byte) in pattern.iter_mut().enumerate() {
+            *byte = mem.load_byte(mem_addr_add(self.index, offset)?)?;
+        }
+        sound_timer.set_pattern(pattern);
+        Ok(())
+    }
+
+    fn set_pitch_x(&mut self, x: RegId, sound_timer: &mut SoundSystem) -> Result<(), CpuError> {
+        sound_timer.set_pitch(self.registers[x]);
         Ok(())
     }
 
@@ -448,10 +785,17 @@ impl Cpu {
         display: &mut Display,
         mem: &Memory,
     ) -> Result<(), CpuError> {
+        let sprite_len = if imm == 0 { 32 } else { imm as usize };
+        let planes = display.planes().count_ones() as usize;
+        self.check_index_range(sprite_len * planes, mem)?;
         let xv = self.registers[x];
         let yv = self.registers[y];
-        let collision = display.draw(xv, yv, imm, self.index, mem)?;
-        self.set_condition(if collision { 0x01 } else { 0x00 });
+        let drawn = display.draw(xv, yv, imm, self.index, mem, self.quirks.clip_sprites)?;
+        if self.quirks.count_collisions && display.is_hires() {
+            self.set_condition(drawn.collided + drawn.clipped);
+        } else {
+            self.set_condition(if drawn.collided > 0 { 0x01 } else { 0x00 });
+        }
         Ok(())
     }
 }
@@ -461,6 +805,15 @@ fn mem_addr_add(base: MemAddr, offset: MemAddr) -> Result<MemAddr, CpuError> {
         .ok_or(CpuError::MemoryAddressOverflow)
 }
 
+/// Registers from `x` to `y`, both inclusive, in either direction.
+fn reg_range(x: RegId, y: RegId) -> Box<dyn Iterator<Item = RegId>> {
+    if x <= y {
+        Box::new(x..=y)
+    } else {
+        Box::new((y..=x).rev())
+    }
+}
+
 fn char_to_bcd(c: char) -> u8 {
     match c {
         '0'..='9' => (c as u8) - b'0',
@@ -0,0 +1,453 @@
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+
+use cassowary::debugger::{Debugger, Stop};
+use cassowary::movie::Movie;
+use cassowary::render::Tee;
+use cassowary::{
+    Access, CpuError, Instruction, MemAddr, System, WatchAction, WatchEvent, Watchpoint,
+};
+
+use crate::{parse_addr, SystemArgs};
+
+const HELP: &str = "\
+Commands (an empty line repeats the last one):
+  b, break [ADDR]       set a breakpoint at ADDR, or list breakpoints
+  d, delete ADDR        clear the breakpoint at ADDR
+  s, step [N]           execute N instructions (default 1), entering subroutines
+  n, next               execute one instruction, stepping over subroutine calls
+  f, finish             run until the current subroutine returns
+  c, continue           run until a breakpoint is reached
+  back [N]              go back N instructions (default 1)
+  rewind [N]            go back to the start of the frame N frames ago (default 1)
+  watch [ADDR [END] [MODE]]
+                        break on accesses to ADDR..=END, or list watchpoints;
+                        MODE is any of r (read), w (write, default), x (execute)
+  log ADDR [END] [MODE] like watch, but only print the accesses
+  unwatch N             remove watchpoint N
+  r, regs               print registers, I, stack, timers and keys held
+  key KEY               press and hold keypad key KEY (0 to F)
+  keyup KEY             release keypad key KEY
+  record                start recording key presses to a movie
+  record FILE           stop recording and save the movie to FILE
+  play FILE             replay a movie from its starting state
+  save FILE             save the machine state to FILE
+  png FILE              save a screenshot of the display to FILE
+  gif FILE              start recording the display to an animated GIF
+  gif                   stop recording the GIF
+  load FILE             restore the machine state from FILE
+  x ADDR [LEN]          examine LEN bytes of memory (default 64)
+  w, write ADDR BYTE..  write bytes to memory
+  l, list [ADDR] [N]    disassemble N instructions (default 10) around ADDR or PC
+  h, help               show this help
+  q, quit               exit the debugger
+Addresses and values are decimal, or hexadecimal with a 0x prefix.";
+
+/// Instructions shown before the address being listed
+const LIST_CONTEXT: usize = 3;
+
+/// Runs the debugger prompt on stdin until `quit` or the end of input.
+pub fn repl(system: &mut System, options: &SystemArgs) -> Result<(), String> {
+    let mut debugger = Debugger::new();
+    // the GIF being recorded, if any
+    let mut gif = None;
+    let mut last = String::new();
+    println!("Type `help` for a list of commands.");
+    list(system, &debugger, system.cpu().pc(), 1);
+    let stdin = io::stdin();
+    loop {
+        print!("(cassowary) ");
+        io::stdout().flush().map_err(|err| err.to_string())?;
+        let mut line = String::new();
+        if stdin
+            .lock()
+            .read_line(&mut line)
+            .map_err(|err| err.to_string())?
+            == 0
+        {
+            println!();
+            break;
+        }
+        let line = match line.trim() {
+            "" => last.clone(),
+            line => line.to_string(),
+        };
+        last = line.clone();
+        let mut words = line.split_whitespace();
+        let Some(command) = words.next() else {
+            continue;
+        };
+        let args: Vec<&str> = words.collect();
+        if matches!(command, "q" | "quit") {
+            break;
+        }
+        let result = match (command, args.as_slice()) {
+            ("png", [path]) => options.screenshot(system, Path::new(path)),
+            ("gif", []) => stop_gif(system, options, &mut gif),
+            ("gif", [path]) => start_gif(system, options, &mut gif, path),
+            _ => execute(system, &mut debugger, command, &args),
+        };
+        if let Err(err) = result {
+            println!("{}", err);
+        }
+    }
+    if gif.is_some() {
+        stop_gif(system, options, &mut gif)?;
+    }
+    Ok(())
+}
+
+/// Starts recording the display to a GIF, as well as showing it.
+fn start_gif(
+    system: &mut System,
+    options: &SystemArgs,
+    gif: &mut Option<String>,
+    path: &str,
+) -> Result<(), String> {
+    if let Some(recording) = gif {
+        return Err(format!("already recording to {}", recording));
+    }
+    let recorder = options.gif_recorder(Path::new(path))?;
+    system.set_renderer(Box::new(Tee::new(options.renderer(), Box::new(recorder))));
+    *gif = Some(path.to_string());
+    Ok(())
+}
+
+/// Stops recording the GIF and writes it out.
+fn stop_gif(
+    system: &mut System,
+    options: &SystemArgs,
+    gif: &mut Option<String>,
+) -> Result<(), String> {
+    let path = gif
+        .take()
+        .ok_or_else(|| "not recording a GIF".to_string())?;
+    system
+        .set_renderer(options.renderer())
+        .finish()
+        .map_err(|err| format!("{}: {}", path, err))
+}
+
+fn execute(
+    system: &mut System,
+    debugger: &mut Debugger,
+    command: &str,
+    args: &[&str],
+) -> Result<(), String> {
+    let stop = match (command, args) {
+        ("b" | "break", []) => {
+            for addr in debugger.breakpoints() {
+                println!("  {:03X}", addr);
+            }
+            return Ok(());
+        }
+        ("b" | "break", [addr]) => {
+            let addr = parse_addr(addr)?;
+            if !debugger.set_breakpoint(addr) {
+                println!("breakpoint at {:03X} already set", addr);
+            }
+            return Ok(());
+        }
+        ("d" | "delete", [addr]) => {
+            let addr = parse_addr(addr)?;
+            if !debugger.clear_breakpoint(addr) {
+                println!("no breakpoint at {:03X}", addr);
+            }
+            return Ok(());
+        }
+        ("s" | "step", [] | [_]) => {
+            let count = args.first().map(|n| parse_addr(n)).transpose()?;
+            let count = count.unwrap_or(1);
+            if count == 0 {
+                return Err("step count must be at least 1".to_string());
+            }
+            step_n(system, debugger, count)
+        }
+        ("n" | "next", []) => debugger.step_over(system),
+        ("f" | "finish", []) => debugger.step_out(system),
+        ("c" | "continue", []) => debugger.cont(system),
+        ("watch", []) | ("log", []) => {
+            for (n, wp) in system.memory().watchpoints().iter().enumerate() {
+                let mode: String = [(wp.read, 'r'), (wp.write, 'w'), (wp.execute, 'x')]
+                    .iter()
+                    .filter_map(|(on, c)| on.then_some(*c))
+                    .collect();
+                let action = match wp.action {
+                    WatchAction::Break => "watch",
+                    WatchAction::Log => "log",
+                };
+                println!(
+                    "  {}: {} {:03X}-{:03X} {}",
+                    n, action, wp.start, wp.end, mode
+                );
+            }
+            return Ok(());
+        }
+        ("watch" | "log", [_, ..]) => {
+            let action = match command {
+                "watch" => WatchAction::Break,
+                _ => WatchAction::Log,
+            };
+            system
+                .memory_mut()
+                .add_watchpoint(watchpoint(args, action)?);
+            return Ok(());
+        }
+        ("unwatch", [n]) => {
+            let n = parse_addr(n)?;
+            if system.memory_mut().remove_watchpoint(n).is_none() {
+                println!("no watchpoint {}", n);
+            }
+            return Ok(());
+        }
+        ("key", [key]) => {
+            system.key_down(parse_key(key)?);
+            return Ok(());
+        }
+        ("keyup", [key]) => {
+            system.key_up(parse_key(key)?);
+            return Ok(());
+        }
+        ("record", []) => {
+            system.start_recording();
+            return Ok(());
+        }
+        ("record", [path]) => {
+            let movie = system
+                .stop_recording()
+                .ok_or_else(|| "not recording".to_string())?;
+            fs::write(path, movie.to_bytes()).map_err(|err| format!("{}: {}", path, err))?;
+            return Ok(());
+        }
+        ("play", [path]) => {
+            let bytes = fs::read(path).map_err(|err| format!("{}: {}", path, err))?;
+            let movie = Movie::from_bytes(&bytes).map_err(|err| format!("{}: {}", path, err))?;
+            system
+                .play_movie(movie)
+                .map_err(|err| format!("{}: {}", path, err))?;
+            list(system, debugger, system.cpu().pc(), 1);
+            return Ok(());
+        }
+        ("save", [path]) => {
+            fs::write(path, system.save_state()).map_err(|err| format!("{}: {}", path, err))?;
+            return Ok(());
+        }
+        ("load", [path]) => {
+            let state = fs::read(path).map_err(|err| format!("{}: {}", path, err))?;
+            system
+                .load_state(&state)
+                .map_err(|err| format!("{}: {}", path, err))?;
+            list(system, debugger, system.cpu().pc(), 1);
+            return Ok(());
+        }
+        ("back" | "rewind", [] | [_]) => {
+            let n = args.first().map(|n| parse_addr(n)).transpose()?;
+            let n = n.unwrap_or(1) as u64;
+            let result = match command {
+                "back" => system.rewind_instructions(n),
+                _ => system.rewind_frames(n),
+            };
+            // accesses made while executing up to the target again
+            system.memory_mut().take_watch_events();
+            result.map_err(|err| err.to_string())?;
+            list(system, debugger, system.cpu().pc(), 1);
+            return Ok(());
+        }
+        ("r" | "regs", []) => {
+            registers(system);
+            return Ok(());
+        }
+        ("x", [addr] | [addr, _]) => {
+            let addr = parse_addr(addr)?;
+            let len = args.get(1).map(|n| parse_addr(n)).transpose()?;
+            examine(system, addr, len.unwrap_or(64))?;
+            return Ok(());
+        }
+        ("w" | "write", [addr, bytes @ ..]) if !bytes.is_empty() => {
+            let addr = parse_addr(addr)?;
+            let bytes = bytes
+                .iter()
+                .map(|b| {
+                    parse_addr(b)
+                        .and_then(|b| u8::try_from(b).map_err(|_| format!("invalid byte {}", b)))
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            system
+                .memory_mut()
+                .set_mem_from(addr, &bytes)
+                .map_err(|err| err.to_string())?;
+            return Ok(());
+        }
+        ("l" | "list", [] | [_] | [_, _]) => {
+            let addr = args.first().map(|a| parse_addr(a)).transpose()?;
+            let count = args.get(1).map(|n| parse_addr(n)).transpose()?;
+            let addr = addr.unwrap_or_else(|| system.cpu().pc());
+            list(system, debugger, addr, count.unwrap_or(10));
+            return Ok(());
+        }
+        ("h" | "help", _) => {
+            println!("{}", HELP);
+            return Ok(());
+        }
+        _ => return Err(format!("invalid command {:?} (try `help`)", command)),
+    };
+    let stop = stop.map_err(|err| format!("{} (PC: {:03X})", err, system.cpu().pc()));
+    for event in debugger.take_log() {
+        match event.action {
+            WatchAction::Break => println!("watchpoint: {}", describe(&event)),
+            WatchAction::Log => println!("{}", describe(&event)),
+        }
+    }
+    match stop? {
+        Stop::Done => {}
+        Stop::Breakpoint(addr) => println!("breakpoint at {:03X}", addr),
+        Stop::Halted => println!("halted"),
+        Stop::AwaitingKey => println!("waiting for a key press"),
+        Stop::Looping(addr) => println!("stuck in a loop at {:03X}", addr),
+        // printed with the log above
+        Stop::Watchpoint(_) => {}
+    }
+    list(system, debugger, system.cpu().pc(), 1);
+    Ok(())
+}
+
+/// Parses `ADDR [END] [MODE]`.
+fn watchpoint(args: &[&str], action: WatchAction) -> Result<Watchpoint, String> {
+    let is_mode = |arg: &&str| arg.chars().all(|c| matches!(c, 'r' | 'w' | 'x'));
+    let (mode, addrs) = match args.split_last() {
+        Some((mode, addrs)) if is_mode(mode) => (*mode, addrs),
+        _ => ("w", args),
+    };
+    let (start, end) = match addrs {
+        [addr] => (parse_addr(addr)?, parse_addr(addr)?),
+        [start, end] => (parse_addr(start)?, parse_addr(end)?),
+        _ => return Err("usage: watch ADDR [END] [MODE]".to_string()),
+    };
+    if end < start {
+        return Err(format!("end {:03X} is before start {:03X}", end, start));
+    }
+    Ok(Watchpoint {
+        start,
+        end,
+        read: mode.contains('r'),
+        write: mode.contains('w'),
+        execute: mode.contains('x'),
+        action,
+    })
+}
+
+fn describe(event: &WatchEvent) -> String {
+    let access = match event.access {
+        Access::Read => "read",
+        Access::Write => "write",
+        Access::Execute => "execute",
+    };
+    format!(
+        "{} {:03X}: {:02X} -> {:02X} (PC: {:03X})",
+        access, event.addr, event.old, event.new, event.pc
+    )
+}
+
+fn step_n(system: &mut System, debugger: &mut Debugger, count: usize) -> Result<Stop, CpuError> {
+    for _ in 1..count {
+        match debugger.step_in(system)? {
+            Stop::Done => {}
+            stop => return Ok(stop),
+        }
+    }
+    debugger.step_in(system)
+}
+
+fn registers(system: &System) {
+    let cpu = system.cpu();
+    println!(
+        "PC: {:03X}  I: {:03X}  DT: {:02X}  ST: {:02X}  frame: {}  cycle: {}",
+        cpu.pc(),
+        cpu.index(),
+        system.delay_timer(),
+        system.sound_timer(),
+        system.frame(),
+        system.cycles()
+    );
+    for row in 0..4 {
+        for reg in (row * 4)..(row * 4 + 4) {
+            print!("  V{:X}: {:02X}", reg, cpu.get_register(reg));
+        }
+        println!();
+    }
+    let stack: Vec<String> = cpu
+        .stack()
+        .iter()
+        .map(|addr| format!("{:03X}", addr))
+        .collect();
+    println!("Stack: [{}]", stack.join(", "));
+    let keys: Vec<String> = (0..16)
+        .filter(|key| system.keyboard().is_down(*key))
+        .map(|key| format!("{:X}", key))
+        .collect();
+    println!("Keys: [{}]", keys.join(", "));
+}
+
+/// Parses a keypad key as a single hexadecimal digit.
+fn parse_key(s: &str) -> Result<u8, String> {
+    match s.chars().collect::<Vec<_>>()[..] {
+        [digit] => digit.to_digit(16).map(|key| key as u8),
+        _ => None,
+    }
+    .ok_or_else(|| format!("invalid key {} (expected 0 to F)", s))
+}
+
+fn examine(system: &System, addr: MemAddr, len: usize) -> Result<(), String> {
+    let bytes = system
+        .memory()
+        .get_mem(addr, len)
+        .map_err(|err| err.to_string())?;
+    for (offset, row) in (0..len).step_by(16).zip(bytes.chunks(16)) {
+        let hex: Vec<String> = row.iter().map(|b| format!("{:02X}", b)).collect();
+        println!("{:03X}: {}", addr + offset, hex.join(" "));
+    }
+    Ok(())
+}
+
+/// Disassembles `count` instructions from a few instructions before `addr`.
+///
+/// Instructions are decoded linearly, so the ones before `addr` may be
+/// misaligned data.
+fn list(system: &System, debugger: &Debugger, addr: MemAddr, count: usize) {
+    let mem = system.memory();
+    let word = |addr: MemAddr| {
+        mem.get_mem(addr, 2)
+            .ok()
+            .map(|w| u16::from_be_bytes([w[0], w[1]]))
+    };
+    let long_index_load = system.config().quirks.long_index_load;
+    let context = if count > 1 { LIST_CONTEXT } else { 0 };
+    let mut addr = addr.saturating_sub(context * 2);
+    for _ in 0..count {
+        let Some(opcode) = word(addr) else {
+            break;
+        };
+        let (instr, len) = match word(addr + 2) {
+            Some(operand) if long_index_load && Instruction::is_long(opcode) => {
+                (Instruction::decode_long(opcode, operand), 4)
+            }
+            _ => (Instruction::decode(opcode), 2),
+        };
+        let current = if addr == system.cpu().pc() {
+            "=>"
+        } else {
+            "  "
+        };
+        let breakpoint = if debugger.is_breakpoint(addr) {
+            '*'
+        } else {
+            ' '
+        };
+        println!(
+            "{}{} {:03X}: {:04X}  {}",
+            current, breakpoint, addr, opcode, instr
+        );
+        addr += len;
+    }
+}
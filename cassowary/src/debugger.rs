@@ -0,0 +1,287 @@
+//! Breakpoints and stepping on top of [`System::step`].
+//!
+//! Step over and step out follow the subroutine calls on the [`Cpu`](crate::Cpu)
+//! stack: stepping over a `CALL` runs until the stack is back to its depth
+//! before the call, stepping out runs until it is one shallower.
+//!
+//! Memory [`Watchpoint`](crate::Watchpoint)s set to break stop execution after
+//! the instruction that hit them; all hits are collected by the debugger.
+
+use std::collections::BTreeSet;
+
+use crate::cpu::{CpuError, Step};
+use crate::instructions::{Instruction, MemAddr};
+use crate::memory::{WatchAction, WatchEvent};
+use crate::System;
+
+/// Why the debugger stopped executing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stop {
+    /// The requested step completed
+    Done,
+    /// PC reached a breakpoint
+    Breakpoint(MemAddr),
+    /// The CPU halted
+    Halted,
+    /// The CPU is waiting for a key press (`LDK VX`)
+    AwaitingKey,
+    /// The CPU is stuck in a jump to itself
+    Looping(MemAddr),
+    /// An access hit a watchpoint set to break
+    Watchpoint(WatchEvent),
+}
+
+pub struct Debugger {
+    breakpoints: BTreeSet<MemAddr>,
+    log: Vec<WatchEvent>,
+}
+
+impl Default for Debugger {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Self {
+            breakpoints: BTreeSet::new(),
+            log: Vec::new(),
+        }
+    }
+
+    /// Sets a breakpoint; returns `false` if it was already set.
+    pub fn set_breakpoint(&mut self, addr: MemAddr) -> bool {
+        self.breakpoints.insert(addr)
+    }
+
+    /// Clears a breakpoint; returns `false` if it was not set.
+    pub fn clear_breakpoint(&mut self, addr: MemAddr) -> bool {
+        self.breakpoints.remove(&addr)
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = MemAddr> + '_ {
+        self.breakpoints.iter().copied()
+    }
+
+    pub fn is_breakpoint(&self, addr: MemAddr) -> bool {
+        self.breakpoints.contains(&addr)
+    }
+
+    /// Returns and forgets the watchpoint hits so far, including those that
+    /// stopped execution.
+    pub fn take_log(&mut self) -> Vec<WatchEvent> {
+        std::mem::take(&mut self.log)
+    }
+
+    /// Executes a single instruction, entering subroutines.
+    pub fn step_in(&mut self, system: &mut System) -> Result<Stop, CpuError> {
+        let (_, stop) = self.step(system)?;
+        Ok(stop.unwrap_or(Stop::Done))
+    }
+
+    /// Executes a single instruction, running a called subroutine to its return.
+    pub fn step_over(&mut self, system: &mut System) -> Result<Stop, CpuError> {
+        let depth = system.cpu().stack().len();
+        let (step, stop) = self.step(system)?;
+        if let Some(stop) = stop {
+            return Ok(stop);
+        }
+        match step.instruction {
+            Instruction::Call(_) => {
+                self.run_until(system, |system| system.cpu().stack().len() <= depth)
+            }
+            _ => Ok(Stop::Done),
+        }
+    }
+
+    /// Runs until the current subroutine returns.
+    ///
+    /// Outside of any subroutine this is the same as [`Debugger::cont`].
+    pub fn step_out(&mut self, system: &mut System) -> Result<Stop, CpuError> {
+        match system.cpu().stack().len() {
+            0 => self.cont(system),
+            depth => self.run_until(system, |system| system.cpu().stack().len() < depth),
+        }
+    }
+
+    /// Runs until a breakpoint is reached or execution cannot continue.
+    pub fn cont(&mut self, system: &mut System) -> Result<Stop, CpuError> {
+        self.run_until(system, |_| false)
+    }
+
+    fn run_until(
+        &mut self,
+        system: &mut System,
+        done: impl Fn(&System) -> bool,
+    ) -> Result<Stop, CpuError> {
+        loop {
+            let (step, stop) = self.step(system)?;
+            if let Some(stop) = stop {
+                return Ok(stop);
+            }
+            if done(system) {
+                return Ok(Stop::Done);
+            }
+            if self.is_breakpoint(step.pc_after) {
+                return Ok(Stop::Breakpoint(step.pc_after));
+            }
+        }
+    }
+
+    /// Executes a single instruction, returning why execution must stop, if it must.
+    fn step(&mut self, system: &mut System) -> Result<(Step, Option<Stop>), CpuError> {
+        let step = system.step()?;
+        let mut stop = None;
+        for event in system.memory_mut().take_watch_events() {
+            if event.action == WatchAction::Break && stop.is_none() {
+                stop = Some(Stop::Watchpoint(event));
+            }
+            self.log.push(event);
+        }
+        Ok((step, stop.or_else(|| Self::stopped(&step))))
+    }
+
+    fn stopped(step: &Step) -> Option<Stop> {
+        if step.halted {
+            Some(Stop::Halted)
+        } else if step.awaiting_key {
+            Some(Stop::AwaitingKey)
+        } else if step.pc_after == step.pc_before
+            && matches!(step.instruction, Instruction::Jump(_))
+        {
+            Some(Stop::Looping(step.pc_before))
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::{Access, Watchpoint};
+    use crate::tests::assembled;
+
+    const PROGRAM: &str = "\
+        CALL sub
+        LD V1 1
+        HALT
+sub:    LD V0 1
+        LD V0 2
+        RET";
+
+    #[test]
+    fn step_over_runs_the_subroutine() {
+        let mut system = assembled(PROGRAM);
+        let mut debugger = Debugger::new();
+        assert_eq!(debugger.step_over(&mut system).unwrap(), Stop::Done);
+        assert_eq!(system.cpu().pc(), 0x202);
+        assert_eq!(system.cpu().get_register(0), 2);
+    }
+
+    #[test]
+    fn step_out_returns_from_the_subroutine() {
+        let mut system = assembled(PROGRAM);
+        let mut debugger = Debugger::new();
+        debugger.step_in(&mut system).unwrap();
+        assert_eq!(system.cpu().pc(), 0x206);
+        assert_eq!(debugger.step_out(&mut system).unwrap(), Stop::Done);
+        assert_eq!(system.cpu().pc(), 0x202);
+        assert!(system.cpu().stack().is_empty());
+    }
+
+    #[test]
+    fn breakpoints_stop_execution() {
+        let mut system = assembled(PROGRAM);
+        let mut debugger = Debugger::new();
+        assert!(debugger.set_breakpoint(0x208));
+        assert!(!debugger.set_breakpoint(0x208));
+        assert_eq!(
+            debugger.step_over(&mut system).unwrap(),
+            Stop::Breakpoint(0x208)
+        );
+        assert_eq!(system.cpu().get_register(0), 1);
+        assert!(debugger.clear_breakpoint(0x208));
+        assert_eq!(debugger.cont(&mut system).unwrap(), Stop::Halted);
+        assert_eq!(system.cpu().get_register(1), 1);
+    }
+
+    #[test]
+    fn jump_to_itself_stops() {
+        let mut system = assembled("LD V0 1\nloop: JP loop");
+        let mut debugger = Debugger::new();
+        assert_eq!(debugger.cont(&mut system).unwrap(), Stop::Looping(0x202));
+    }
+
+    /// Reads and then overwrites `data` (0xAA) at 0x20A.
+    const ACCESSES: &str = "\
+        LD I data
+        LDREGS V0
+        LD V0 7
+        STREGS V0
+        HALT
+data:   db 0xAA";
+
+    fn watch(start: MemAddr, mode: &str, action: WatchAction) -> Watchpoint {
+        Watchpoint {
+            start,
+            end: start + 1,
+            read: mode.contains('r'),
+            write: mode.contains('w'),
+            execute: mode.contains('x'),
+            action,
+        }
+    }
+
+    fn event(access: Access, addr: MemAddr, pc: MemAddr, old: u8, new: u8) -> WatchEvent {
+        WatchEvent {
+            access,
+            addr,
+            pc,
+            old,
+            new,
+            action: WatchAction::Log,
+        }
+    }
+
+    #[test]
+    fn logged_accesses() {
+        let mut system = assembled(ACCESSES);
+        system
+            .memory_mut()
+            .add_watchpoint(watch(0x20A, "rw", WatchAction::Log));
+        system
+            .memory_mut()
+            .add_watchpoint(watch(0x204, "x", WatchAction::Log));
+        let mut debugger = Debugger::new();
+        assert_eq!(debugger.cont(&mut system).unwrap(), Stop::Halted);
+        assert_eq!(
+            debugger.take_log(),
+            [
+                event(Access::Read, 0x20A, 0x202, 0xAA, 0xAA),
+                event(Access::Execute, 0x204, 0x204, 0x60, 0x60),
+                event(Access::Execute, 0x205, 0x204, 0x07, 0x07),
+                event(Access::Write, 0x20A, 0x206, 0xAA, 0x07),
+            ]
+        );
+        assert!(debugger.take_log().is_empty());
+    }
+
+    #[test]
+    fn breaking_watchpoints_stop_after_the_access() {
+        let mut system = assembled(ACCESSES);
+        system
+            .memory_mut()
+            .add_watchpoint(watch(0x20A, "w", WatchAction::Break));
+        let mut debugger = Debugger::new();
+        let write = WatchEvent {
+            action: WatchAction::Break,
+            ..event(Access::Write, 0x20A, 0x206, 0xAA, 0x07)
+        };
+        assert_eq!(debugger.cont(&mut system).unwrap(), Stop::Watchpoint(write));
+        assert_eq!(system.cpu().pc(), 0x208);
+        assert_eq!(debugger.take_log(), [write]);
+        assert_eq!(debugger.cont(&mut system).unwrap(), Stop::Halted);
+    }
+}
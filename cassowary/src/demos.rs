@@ -0,0 +1,74 @@
+//! Built-in sample programs, in the `progloader` hex format.
+
+use cassowary::progloader;
+use cassowary::{Memory, MemoryError};
+
+pub struct Demo {
+    pub name: &'static str,
+    pub description: &'static str,
+    program: &'static str,
+}
+
+impl Demo {
+    pub fn load(&self, mem: &mut Memory) -> Result<(), MemoryError> {
+        progloader::load_from_hex(self.program, mem)
+    }
+}
+
+pub const DEMOS: [Demo; 5] = [
+    Demo {
+        name: "hex-sprite",
+        description: "display a hex sprite",
+        program: "# Display 'E'
+                  # LDR $1 3; LDR $2 2; LDR $3 E; LDSPR $3; DISP $1 $2 5
+                  0200   6103 6202 630E F329 D125
+                 ",
+    },
+    Demo {
+        // This is an example from The CHIP-8 Classic Manual
+        // http://www.CHIP-8.com/
+        name: "hex-to-decimal",
+        description: "hex to decimal",
+        program: "0200   00E0 6380 6400 6500 A500 F333 F265 F029
+                  0210   D455 F129 7408 D455 F229 7408 D455 F000
+                 ",
+    },
+    Demo {
+        name: "timer-sprites",
+        description: "play sound for c. 1 s and display sprites with delay",
+        program: "# LDR $3 @60; SETSOUND $3;
+                  0200  633C F318
+                  # LDR $7 A; CALL 300
+                  0204  670A 2300
+                  # LDR $4 @30; SETDELAY $4;
+                  0208  641E F415
+                  # GETDELAY $4; SKIPEQ $4, 0 ; JMP 20C
+                  020C  F407 3400 120C
+                  # ADDI $7 1; CALL 300
+                  0212  7701 2300
+
+                  # DISPCLR; LDR 5 3; LDR 6 2; LDSPR 7; DISP 5 6 5; RET;
+                  0300 00E0 6503 6602 F729 D565 00EE
+                 ",
+    },
+    Demo {
+        name: "scratch",
+        description: "random scratch",
+        program: "# LDR $3 @10; SETSOUND $3;
+                  0200  630A F318
+                  0204  7A01 1204
+                 ",
+    },
+    Demo {
+        // This is an example from Tim McNamara's "Rust in Action"
+        name: "double-sum",
+        description: "add V1 to V0 twice in a subroutine, twice",
+        program: "0200   2100 2100 0000
+                  0100   8014 8014 00EE
+                 ",
+    },
+];
+
+pub fn find(name: &str) -> Option<&'static Demo> {
+    DEMOS.iter().find(|demo| demo.name == name)
+}
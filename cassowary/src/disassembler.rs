@@ -0,0 +1,237 @@
+//! Disassembler producing source for the [`assembler`](crate::assembler).
+//!
+//! Rather than decoding every word, the disassembler follows the control flow
+//! (jumps, calls and skips) from the entry point, so only reachable
+//! instructions are rendered as code. Everything else is rendered as `db`
+//! data; data referenced by `LD I` (most likely sprites) is annotated with a
+//! bitmap preview.
+//!
+//! Targets of `JPV0` can only be known at run time: the table base is
+//! followed as code, but its other entries are not.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+use crate::instructions::{Instruction, MemAddr};
+
+/// Bytes per `db` line for data that does not look like a sprite
+const DATA_LINE: usize = 8;
+/// Longest data block annotated as a sprite (a 16x16 sprite)
+const MAX_SPRITE_LEN: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum LabelKind {
+    Subroutine,
+    Code,
+    Data,
+}
+
+struct Code {
+    len: usize,
+    instr: Instruction,
+    opcode: u32,
+}
+
+/// Disassembles `rom` loaded at `origin`, following the control flow from `entry`.
+///
+/// `F000 NNNN` is the two word `LDL I NNNN` only if `long_index_load` (XO-CHIP,
+/// see [`Quirks::long_index_load`](crate::quirks::Quirks::long_index_load)).
+pub fn disassemble(rom: &[u8], origin: MemAddr, entry: MemAddr, long_index_load: bool) -> String {
+    let end = origin + rom.len();
+    let word_at = |addr: MemAddr| -> Option<u16> {
+        if addr >= origin && addr + 2 <= end {
+            let i = addr - origin;
+            Some(u16::from_be_bytes([rom[i], rom[i + 1]]))
+        } else {
+            None
+        }
+    };
+
+    let mut code: BTreeMap<MemAddr, Code> = BTreeMap::new();
+    // every byte covered by an instruction
+    let mut covered: BTreeSet<MemAddr> = BTreeSet::new();
+    let mut labels: BTreeMap<MemAddr, LabelKind> = BTreeMap::new();
+    let mut label = |addr: MemAddr, kind: LabelKind| {
+        let entry = labels.entry(addr).or_insert(kind);
+        *entry = (*entry).min(kind);
+    };
+
+    let mut pending = vec![entry];
+    while let Some(addr) = pending.pop() {
+        if covered.contains(&addr) {
+            continue;
+        }
+        let Some(opcode) = word_at(addr) else {
+            continue;
+        };
+        let (instr, len, opcode) = if long_index_load && Instruction::is_long(opcode) {
+            match word_at(addr + 2) {
+                Some(operand) => (
+                    Instruction::decode_long(opcode, operand),
+                    4,
+                    ((opcode as u32) << 16) | operand as u32,
+                ),
+                None => continue,
+            }
+        } else {
+            (Instruction::decode(opcode), 2, opcode as u32)
+        };
+        if matches!(instr, Instruction::Unsupported(_))
+            || (addr..(addr + len)).any(|a| covered.contains(&a))
+        {
+            continue;
+        }
+        covered.extend(addr..(addr + len));
+        code.insert(addr, Code { len, instr, opcode });
+
+        let next = addr + len;
+        match instr {
+            Instruction::Jump(target) => {
+                label(target, LabelKind::Code);
+                pending.push(target);
+            }
+            Instruction::JumpV0(target) => {
+                label(target, LabelKind::Code);
+                pending.push(target);
+            }
+            Instruction::Call(target) => {
+                label(target, LabelKind::Subroutine);
+                pending.push(target);
+                pending.push(next);
+            }
+            Instruction::SkipIfEqX(..)
+            | Instruction::SkipIfNeX(..)
+            | Instruction::SkipIfEqXY(..)
+            | Instruction::SkipIfNeXY(..)
+            | Instruction::SkipIfKeyEqX(_)
+            | Instruction::SkipIfKeyNeX(_) => {
+                pending.push(next);
+                let skipped = match word_at(next) {
+                    Some(opcode) if long_index_load && Instruction::is_long(opcode) => 4,
+                    _ => 2,
+                };
+                pending.push(next + skipped);
+            }
+            Instruction::Ret | Instruction::Halt | Instruction::Exit => {}
+            Instruction::SetI(target) | Instruction::SetILong(target) => {
+                label(target, LabelKind::Data);
+                pending.push(next);
+            }
+            _ => pending.push(next),
+        }
+    }
+
+    // only addresses at the start of an instruction or in data can be labeled
+    labels.retain(|addr, _| {
+        *addr >= origin && *addr < end && (code.contains_key(addr) || !covered.contains(addr))
+    });
+    let name = |addr: MemAddr| -> Option<String> {
+        labels.get(&addr).map(|kind| match kind {
+            LabelKind::Subroutine => format!("sub_{:03X}", addr),
+            LabelKind::Code => format!("L{:03X}", addr),
+            LabelKind::Data => format!("data_{:03X}", addr),
+        })
+    };
+
+    let mut out = String::new();
+    writeln!(out, "        org {:#05X}", origin).unwrap();
+    let mut addr = origin;
+    while addr < end {
+        if let Some(label) = name(addr) {
+            writeln!(out, "{}:", label).unwrap();
+        }
+        if let Some(c) = code.get(&addr) {
+            let target = |addr: MemAddr| name(addr).unwrap_or_else(|| format!("{:#05X}", addr));
+            let text = match c.instr {
+                Instruction::Jump(a) => format!("JP {}", target(a)),
+                Instruction::JumpV0(a) => format!("JPV0 {}", target(a)),
+                Instruction::Call(a) => format!("CALL {}", target(a)),
+                Instruction::SetI(a) => format!("LD I {}", target(a)),
+                Instruction::SetILong(a) => format!("LDL I {}", target(a)),
+                instr => instr.to_string(),
+            };
+            let opcode = if c.len == 4 {
+                format!("{:08X}", c.opcode)
+            } else {
+                format!("{:04X}", c.opcode)
+            };
+            writeln!(out, "        {:24}; {:03X}: {}", text, addr, opcode).unwrap();
+            addr += c.len;
+            continue;
+        }
+
+        // data up to the next instruction or label
+        let data_end = (addr + 1..end)
+            .find(|a| code.contains_key(a) || labels.contains_key(a))
+            .unwrap_or(end);
+        let data = &rom[(addr - origin)..(data_end - origin)];
+        let sprite = labels.get(&addr) == Some(&LabelKind::Data) && data.len() <= MAX_SPRITE_LEN;
+        if sprite {
+            for byte in data {
+                let text = format!("db {:#04X}", byte);
+                writeln!(out, "        {:24}; {}", text, bitmap(*byte)).unwrap();
+            }
+        } else {
+            for (offset, line) in (0..data.len())
+                .step_by(DATA_LINE)
+                .zip(data.chunks(DATA_LINE))
+            {
+                let bytes: Vec<String> = line.iter().map(|b| format!("{:#04X}", b)).collect();
+                let text = format!("db {}", bytes.join(", "));
+                writeln!(out, "        {:24}; {:03X}", text, addr + offset).unwrap();
+            }
+        }
+        addr = data_end;
+    }
+    out
+}
+
+fn bitmap(byte: u8) -> String {
+    (0..8)
+        .map(|bit| if byte & (0x80 >> bit) != 0 { '#' } else { '.' })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::assemble;
+
+    fn round_trip(source: &str) -> String {
+        let program = assemble(source).unwrap();
+        let rom = program.to_binary();
+        let text = disassemble(&rom, 0x200, program.start(), true);
+        assert_eq!(assemble(&text).unwrap().to_binary(), rom, "{}", text);
+        text
+    }
+
+    #[test]
+    fn skip_over_long_instruction() {
+        let text =
+            round_trip("org 0x200\nLD V0 1\nSE V0 1\nLDL I 0x300\nloop: JP loop\nLDL I loop\nHALT");
+        assert!(text.contains("LDL I 0x300"), "{}", text);
+        // the word after a skipped long instruction is code, its operand is not
+        assert!(text.contains("L208:\n        JP L208"), "{}", text);
+    }
+
+    #[test]
+    fn jump_table() {
+        let text = round_trip(
+            "org 0x200\nLD V0 2\nJPV0 table\ndb 0xFF\ntable: JP one\nJP one\none: LD I sprite\nHALT\nsprite: db 0xF0, 0x90",
+        );
+        assert!(text.contains("JPV0 L205"), "{}", text);
+        assert!(text.contains("db 0xFF"), "{}", text);
+        assert!(
+            text.contains("db 0xF0                 ; ####...."),
+            "{}",
+            text
+        );
+    }
+
+    #[test]
+    fn f000_halts_outside_xo_chip() {
+        let text = disassemble(&[0xF0, 0x00, 0x12, 0x34], 0x200, 0x200, false);
+        assert!(text.contains("HALT"), "{}", text);
+        assert!(!text.contains("LDL"), "{}", text);
+    }
+}
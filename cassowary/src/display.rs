@@ -1,22 +1,151 @@
+use crate::state::DisplayState;
 use crate::{instructions::MemAddr, memory::MemoryError, Memory};
 
-const WIDTH: usize = 64;
-const HEIGHT: usize = 32;
+pub const LORES_WIDTH: usize = 64;
+pub const LORES_HEIGHT: usize = 32;
+pub const HIRES_WIDTH: usize = 128;
+pub const HIRES_HEIGHT: usize = 64;
 
-pub struct Display([[u8; WIDTH]; HEIGHT]);
+/// Number of XO-CHIP bitplanes
+pub const PLANES: usize = 2;
+
+/// SUPER-CHIP scrolls left and right by 4 pixels
+const SCROLL_X: usize = 4;
+
+/// Number of rows of a drawn sprite
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct Drawn {
+    /// rows in which a lit pixel was turned off
+    pub(crate) collided: u8,
+    /// rows clipped at the bottom of the screen
+    pub(crate) clipped: u8,
+}
+
+/// The framebuffer: what is drawn, not how it is shown (see [`crate::render`]).
+///
+/// The display is either 64x32 (low resolution) or 128x64 pixels
+/// (SUPER-CHIP high resolution). Only the top left `width() x height()`
+/// part of the buffer is in use.
+///
+/// Each pixel holds one bit per bitplane (XO-CHIP), plane 1 being the least
+/// significant bit. Drawing, clearing and scrolling only affect the selected planes.
+pub struct Display {
+    pixels: [[u8; HIRES_WIDTH]; HIRES_HEIGHT],
+    hires: bool,
+    planes: u8,
+    changed: bool,
+}
+
+impl Default for Display {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 impl Display {
     pub fn new() -> Self {
-        Self([[0; WIDTH]; HEIGHT])
+        Self {
+            pixels: [[0; HIRES_WIDTH]; HIRES_HEIGHT],
+            hires: false,
+            planes: 0x01,
+            changed: false,
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        if self.hires {
+            HIRES_WIDTH
+        } else {
+            LORES_WIDTH
+        }
+    }
+
+    pub fn height(&self) -> usize {
+        if self.hires {
+            HIRES_HEIGHT
+        } else {
+            LORES_HEIGHT
+        }
     }
 
+    pub fn is_hires(&self) -> bool {
+        self.hires
+    }
+
+    /// Value of the pixel at (`col`, `row`), one bit per bitplane.
+    pub fn pixel(&self, col: usize, row: usize) -> u8 {
+        self.pixels[row][col]
+    }
+
+    /// The pixels of row `row`, `width()` of them.
+    pub fn row(&self, row: usize) -> &[u8] {
+        &self.pixels[row][..self.width()]
+    }
+
+    /// Bitmask of the selected planes
+    pub fn planes(&self) -> u8 {
+        self.planes
+    }
+
+    /// Clears the selected planes.
     pub fn clear(&mut self) {
-        for row in &mut self.0 {
-            row.fill(0);
+        self.clear_planes(self.planes);
+    }
+
+    fn clear_planes(&mut self, planes: u8) {
+        for row in &mut self.pixels {
+            for pixel in row.iter_mut() {
+                if *pixel & planes != 0 {
+                    *pixel &= !planes;
+                    self.changed = true;
+                }
+            }
+        }
+    }
+
+    /// Switches between low and high resolution, clearing all planes.
+    pub(crate) fn set_hires(&mut self, hires: bool) {
+        if self.hires != hires {
+            self.hires = hires;
+            self.changed = true;
         }
-        self.refresh();
+        self.clear_planes(0xFF);
     }
 
+    pub(crate) fn select_planes(&mut self, planes: u8) {
+        self.planes = planes & ((1 << PLANES) - 1);
+    }
+
+    pub(crate) fn state(&self) -> DisplayState {
+        DisplayState {
+            pixels: self.pixels,
+            hires: self.hires,
+            planes: self.planes,
+        }
+    }
+
+    pub(crate) fn restore(&mut self, state: &DisplayState) {
+        self.pixels = state.pixels;
+        self.hires = state.hires;
+        self.planes = state.planes;
+        self.changed = true;
+    }
+
+    /// Returns whether any pixel changed since the last call.
+    pub(crate) fn take_changed(&mut self) -> bool {
+        std::mem::take(&mut self.changed)
+    }
+
+    /// XORs a sprite onto the selected planes and returns how many of its rows
+    /// turned off a lit pixel (a collision) and how many were clipped at the
+    /// bottom, in the plane with the most.
+    ///
+    /// Sprites are 8 pixels wide and `height` rows high; a `height` of 0 draws
+    /// a 16x16 sprite (two bytes per row). With several planes selected, the
+    /// sprite data for each plane follows the previous one in memory.
+    ///
+    /// The start position always wraps around the screen; the rest of the sprite
+    /// is either clipped at the edges (`clip`) or wrapped around as well.
     pub(crate) fn draw(
         &mut self,
         x: u8,
@@ -24,37 +153,110 @@ impl Display {
         height: u8,
         start: MemAddr,
         mem: &Memory,
-    ) -> Result<bool, MemoryError> {
-        let x = x as usize % WIDTH;
-        let y = y as usize % HEIGHT;
-        let mut changed = false;
-        for (ri, addr) in (start..(start + height as MemAddr)).enumerate() {
-            let sprite_line = mem.load_byte(addr)?.reverse_bits();
-            let row = (y + ri) % HEIGHT;
-            for ci in 0..8 {
-                let col = (x + ci) % WIDTH;
-                let pixel = (sprite_line >> ci) & 0x01;
-                let old = self.0[row][col] == 1;
-                (&mut self.0[row])[col] = pixel;
-                if !changed {
-                    changed = old ^ (pixel == 1);
+        clip: bool,
+    ) -> Result<Drawn, MemoryError> {
+        let (width, height) = if height == 0 {
+            (16, 16)
+        } else {
+            (8, height as usize)
+        };
+        let sprite_len = height * width / 8;
+        let mut drawn = Drawn::default();
+        let mut start = start;
+        for plane in 0..PLANES {
+            let mask = 1 << plane;
+            if self.planes & mask != 0 {
+                let plane = self.draw_plane(mask, x, y, width, height, start, mem, clip)?;
+                drawn.collided = drawn.collided.max(plane.collided);
+                drawn.clipped = drawn.clipped.max(plane.clipped);
+                start += sprite_len;
+            }
+        }
+        Ok(drawn)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn draw_plane(
+        &mut self,
+        mask: u8,
+        x: u8,
+        y: u8,
+        width: usize,
+        height: usize,
+        start: MemAddr,
+        mem: &Memory,
+        clip: bool,
+    ) -> Result<Drawn, MemoryError> {
+        let (screen_width, screen_height) = (self.width(), self.height());
+        let x = x as usize % screen_width;
+        let y = y as usize % screen_height;
+        let row_bytes = width / 8;
+        let mut drawn = Drawn::default();
+        for ri in 0..height {
+            if clip && y + ri >= screen_height {
+                drawn.clipped = (height - ri) as u8;
+                break;
+            }
+            let row = (y + ri) % screen_height;
+            let mut collision = false;
+            let mut sprite_line = 0_u16;
+            for b in 0..row_bytes {
+                let byte = mem.load_byte(start + ri * row_bytes + b)?;
+                sprite_line = (sprite_line << 8) | byte as u16;
+            }
+            for ci in 0..width {
+                if clip && x + ci >= screen_width {
+                    break;
+                }
+                let col = (x + ci) % screen_width;
+                if (sprite_line >> (width - 1 - ci)) & 0x01 == 0 {
+                    continue;
                 }
+                let old = self.pixels[row][col];
+                collision |= old & mask != 0;
+                self.pixels[row][col] = old ^ mask;
+                self.changed = true;
             }
+            drawn.collided += collision as u8;
         }
-        self.refresh();
-        Ok(changed)
+        Ok(drawn)
+    }
+
+    pub(crate) fn scroll_down(&mut self, n: usize) {
+        self.scroll(|col, row| (row >= n).then(|| (col, row - n)));
     }
 
-    fn refresh(&self) {
-        let border: String = std::iter::repeat('-').take(64).collect();
-        println!("/{}\\", border);
-        for row in self.0 {
-            print!("|");
-            for col in row {
-                print!("{}", if col == 0 { ' ' } else { '*' });
+    pub(crate) fn scroll_up(&mut self, n: usize) {
+        let height = self.height();
+        self.scroll(|col, row| (row + n < height).then(|| (col, row + n)));
+    }
+
+    pub(crate) fn scroll_right(&mut self) {
+        self.scroll(|col, row| (col >= SCROLL_X).then(|| (col - SCROLL_X, row)));
+    }
+
+    pub(crate) fn scroll_left(&mut self) {
+        let width = self.width();
+        self.scroll(|col, row| (col + SCROLL_X < width).then(|| (col + SCROLL_X, row)));
+    }
+
+    /// Moves the selected planes: each pixel takes the value of the pixel
+    /// at `source(col, row)`, or is cleared if there is none.
+    fn scroll<F>(&mut self, source: F)
+    where
+        F: Fn(usize, usize) -> Option<(usize, usize)>,
+    {
+        let (width, height) = (self.width(), self.height());
+        let old = self.pixels;
+        for row in 0..height {
+            for col in 0..width {
+                let moved = source(col, row).map_or(0, |(c, r)| old[r][c]);
+                let pixel = (old[row][col] & !self.planes) | (moved & self.planes);
+                if pixel != old[row][col] {
+                    self.pixels[row][col] = pixel;
+                    self.changed = true;
+                }
             }
-            println!("|");
         }
-        println!("\\{}/", border);
     }
 }
@@ -1,6 +1,13 @@
 //! The information used here is taken from https://en.wikipedia.org/wiki/CHIP-8
 //! and http://devernay.free.fr/hacks/chip8/C8TECH10.HTM
 //!
+//! SUPER-CHIP 1.1 extensions are taken from http://devernay.free.fr/hacks/chip8/schip.txt
+//! and XO-CHIP extensions from https://johnearnest.github.io/Octo/docs/XO-ChipSpecification.html
+//!
+
+use std::fmt;
+
+use thiserror::Error;
 
 pub type MemAddr = usize;
 pub type RegId = usize;
@@ -50,20 +57,20 @@ pub enum Instruction {
     /// `VF <- CARRY` where `CARRY` is 0 on carry, 1 otherwise
     SubXY(RegId, RegId),
 
-    /// `SHR VX`
-    /// `VX <- VX >> 1`
+    /// `SHR VX VY`
+    /// `VX <- VX >> 1` (or `VX <- VY >> 1` with the shift quirk)
     /// `VF <- LSB` where `LSB` is the least significant bit before shift
-    Shr1X(RegId),
+    Shr1X(RegId, RegId),
 
     /// `SUBN VX VY`
     /// `VX <- VY - VX`
     /// `VF <- CARRY` where `CARRY` is 0 on carry, 1 otherwise
     SubYX(RegId, RegId),
 
-    /// `SHL VX`
-    /// `VX <- VX << 1`
+    /// `SHL VX VY`
+    /// `VX <- VX << 1` (or `VX <- VY << 1` with the shift quirk)
     /// `VF <- MSB` where `MSB` is the most significant bit before shift
-    Shl1X(RegId),
+    Shl1X(RegId, RegId),
 
     // Display
     /// `CLS`
@@ -74,8 +81,37 @@ pub enum Instruction {
     /// Display sprite of `N` height at coordinate VX, VY.
     /// The sprite is read from `I`.
     /// `VF <- COLLISTION` `COLLISION` is if any pixels were flipped
+    /// `N = 0` displays a 16x16 sprite (SUPER-CHIP)
     DispDraw(RegId, RegId, u8),
 
+    /// `SCD N`
+    /// scroll display `N` pixels down (SUPER-CHIP)
+    ScrollDown(u8),
+
+    /// `SCU N`
+    /// scroll display `N` pixels up (XO-CHIP)
+    ScrollUp(u8),
+
+    /// `SCR`
+    /// scroll display 4 pixels right (SUPER-CHIP)
+    ScrollRight,
+
+    /// `SCL`
+    /// scroll display 4 pixels left (SUPER-CHIP)
+    ScrollLeft,
+
+    /// `LOW`
+    /// switch to 64x32 low resolution display (SUPER-CHIP)
+    LoRes,
+
+    /// `HIGH`
+    /// switch to 128x64 high resolution display (SUPER-CHIP)
+    HiRes,
+
+    /// `PLANE N`
+    /// select the bitplanes (bitmask `N`) that are drawn, cleared and scrolled (XO-CHIP)
+    SelectPlanes(u8),
+
     // Cond
     /// `SE VX NN`
     /// skip next instruction if `VX = NN`
@@ -99,7 +135,7 @@ pub enum Instruction {
     Jump(MemAddr),
 
     /// `JPV0 NNN`
-    /// `PC <- V0 + NNN`
+    /// `PC <- V0 + NNN` (or `PC <- VX + XNN` with the jump quirk)
     JumpV0(MemAddr),
 
     /// `CALL NNN`
@@ -114,11 +150,11 @@ pub enum Instruction {
     NoOp(u16),
 
     /// `SKP VX`
-    /// `PC <- PC + 2` if `key() = VX`
+    /// `PC <- PC + 2` if key `VX` is held down
     SkipIfKeyEqX(RegId),
 
     /// `SKNP VX`
-    /// `PC <- PC + 2` if `key() != VX`
+    /// `PC <- PC + 2` if key `VX` is not held down
     SkipIfKeyNeX(RegId),
 
     /// `LDDT VX`
@@ -126,7 +162,7 @@ pub enum Instruction {
     GetDelayX(RegId),
 
     /// `LDK VX `
-    /// `VX <- KEY` where `KEY` is current key (await until key press)
+    /// `VX <- KEY` where `KEY` is the next key pressed (await until key press)
     AwaitKeyX(RegId),
 
     /// `STDT VX`
@@ -142,6 +178,13 @@ pub enum Instruction {
     /// `I <- NNN`
     SetI(MemAddr),
 
+    /// `LDL I NNNN`
+    /// `I <- NNNN` where `NNNN` is the 16 bit word following the instruction (XO-CHIP)
+    ///
+    /// This is the only instruction that is two words long; see [`Instruction::decode_long`].
+    /// Outside of XO-CHIP, `F000` is `HALT`.
+    SetILong(MemAddr),
+
     /// `ADDI VX`
     /// `I <- I + VX`
     AddIX(RegId),
@@ -164,6 +207,37 @@ pub enum Instruction {
     /// read values of registers `V0` to `VX` from memory starting at `I`.
     RegLoadIX(RegId),
 
+    /// `LDHSPR VX`
+    /// `I <- BIGSPRITE(VX)` where `BIGSPRITE()` address of 8x10 sprite
+    /// 'hexadecimal sprite` (SUPER-CHIP)
+    BigSpriteAddrIX(RegId),
+
+    /// `STRPL VX`
+    /// write values of registers from `V0` to `VX` to the RPL user flags (SUPER-CHIP)
+    StoreFlagsX(RegId),
+
+    /// `LDRPL VX`
+    /// read values of registers from `V0` to `VX` from the RPL user flags (SUPER-CHIP)
+    LoadFlagsX(RegId),
+
+    /// `SAVE VX VY`
+    /// write values of registers `VX` to `VY` starting at `I` (XO-CHIP)
+    /// `I` is left unchanged
+    RegDumpRangeIXY(RegId, RegId),
+
+    /// `LOAD VX VY`
+    /// read values of registers `VX` to `VY` from memory starting at `I` (XO-CHIP)
+    /// `I` is left unchanged
+    RegLoadRangeIXY(RegId, RegId),
+
+    /// `AUDIO`
+    /// load the 16 byte audio pattern buffer from memory starting at `I` (XO-CHIP)
+    LoadAudio,
+
+    /// `PITCH VX`
+    /// set the audio pattern playback rate to `4000 * 2 ^ ((VX - 64) / 48)` Hz (XO-CHIP)
+    SetPitchX(RegId),
+
     /// `RND VX NN`
     /// `VX <- RAND & NN` where `RAND` is a random number (0 to 255)
     RandX(RegId, u8),
@@ -171,10 +245,263 @@ pub enum Instruction {
     /// halts CPU
     Halt,
 
+    /// `EXIT`
+    /// exit the interpreter, i.e. halts CPU (SUPER-CHIP)
+    Exit,
+
     Unsupported(u16),
 }
 
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EncodeError {
+    #[error("register {0} out of range (V0 to VF)")]
+    InvalidRegister(RegId),
+    #[error("value {value:#X} does not fit in {bits} bits")]
+    OutOfRange { value: usize, bits: u32 },
+    #[error("opcode {0:#06X} is not a pass-through opcode")]
+    NotPassThrough(u16),
+}
+
+fn reg(x: RegId) -> Result<u16, EncodeError> {
+    if x <= 0xF {
+        Ok(x as u16)
+    } else {
+        Err(EncodeError::InvalidRegister(x))
+    }
+}
+
+fn imm(value: usize, bits: u32) -> Result<u16, EncodeError> {
+    if value < (1 << bits) {
+        Ok(value as u16)
+    } else {
+        Err(EncodeError::OutOfRange { value, bits })
+    }
+}
+
+/// Broad kind of an instruction, e.g. to filter a [`trace`](crate::trace).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InstructionClass {
+    /// Loading constants and copying registers
+    Const,
+    /// Arithmetic and bitwise operations
+    Math,
+    Display,
+    /// Conditional skips
+    Cond,
+    /// Jumps, calls and returns
+    Flow,
+    Keyboard,
+    /// Delay and sound timers
+    Timer,
+    /// Audio pattern and pitch
+    Sound,
+    /// Setting `I` and accessing memory or flags through it
+    Memory,
+    Random,
+    /// No-ops, halting and unsupported opcodes
+    System,
+}
+
+impl InstructionClass {
+    pub const ALL: [InstructionClass; 11] = [
+        InstructionClass::Const,
+        InstructionClass::Math,
+        InstructionClass::Display,
+        InstructionClass::Cond,
+        InstructionClass::Flow,
+        InstructionClass::Keyboard,
+        InstructionClass::Timer,
+        InstructionClass::Sound,
+        InstructionClass::Memory,
+        InstructionClass::Random,
+        InstructionClass::System,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            InstructionClass::Const => "const",
+            InstructionClass::Math => "math",
+            InstructionClass::Display => "display",
+            InstructionClass::Cond => "cond",
+            InstructionClass::Flow => "flow",
+            InstructionClass::Keyboard => "keyboard",
+            InstructionClass::Timer => "timer",
+            InstructionClass::Sound => "sound",
+            InstructionClass::Memory => "memory",
+            InstructionClass::Random => "random",
+            InstructionClass::System => "system",
+        }
+    }
+
+    /// Looks up a class by its [`name`](InstructionClass::name).
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|class| class.name() == name)
+    }
+}
+
 impl Instruction {
+    pub fn class(&self) -> InstructionClass {
+        match self {
+            Instruction::AssignXImm(..) | Instruction::AssignXY(..) => InstructionClass::Const,
+            Instruction::AddXImm(..)
+            | Instruction::OrXY(..)
+            | Instruction::AndXY(..)
+            | Instruction::XorXY(..)
+            | Instruction::AddXY(..)
+            | Instruction::SubXY(..)
+            | Instruction::Shr1X(..)
+            | Instruction::SubYX(..)
+            | Instruction::Shl1X(..) => InstructionClass::Math,
+            Instruction::DispClear
+            | Instruction::DispDraw(..)
+            | Instruction::ScrollDown(_)
+            | Instruction::ScrollUp(_)
+            | Instruction::ScrollRight
+            | Instruction::ScrollLeft
+            | Instruction::LoRes
+            | Instruction::HiRes
+            | Instruction::SelectPlanes(_) => InstructionClass::Display,
+            Instruction::SkipIfEqX(..)
+            | Instruction::SkipIfNeX(..)
+            | Instruction::SkipIfEqXY(..)
+            | Instruction::SkipIfNeXY(..) => InstructionClass::Cond,
+            Instruction::Jump(_)
+            | Instruction::JumpV0(_)
+            | Instruction::Call(_)
+            | Instruction::Ret => InstructionClass::Flow,
+            Instruction::SkipIfKeyEqX(_)
+            | Instruction::SkipIfKeyNeX(_)
+            | Instruction::AwaitKeyX(_) => InstructionClass::Keyboard,
+            Instruction::GetDelayX(_) | Instruction::SetDelayX(_) | Instruction::SetSoundX(_) => {
+                InstructionClass::Timer
+            }
+            Instruction::LoadAudio | Instruction::SetPitchX(_) => InstructionClass::Sound,
+            Instruction::SetI(_)
+            | Instruction::SetILong(_)
+            | Instruction::AddIX(_)
+            | Instruction::SpriteAddrIX(_)
+            | Instruction::BigSpriteAddrIX(_)
+            | Instruction::DumpBcdIX(_)
+            | Instruction::RegDumpIX(_)
+            | Instruction::RegLoadIX(_)
+            | Instruction::StoreFlagsX(_)
+            | Instruction::LoadFlagsX(_)
+            | Instruction::RegDumpRangeIXY(..)
+            | Instruction::RegLoadRangeIXY(..) => InstructionClass::Memory,
+            Instruction::RandX(..) => InstructionClass::Random,
+            Instruction::NoOp(_)
+            | Instruction::Halt
+            | Instruction::Exit
+            | Instruction::Unsupported(_) => InstructionClass::System,
+        }
+    }
+
+    /// Returns `true` if `opcode` is the first word of a two word XO-CHIP
+    /// instruction (`F000 NNNN`).
+    pub fn is_long(opcode: u16) -> bool {
+        opcode == 0xF000
+    }
+
+    /// Decodes a two word instruction: `opcode` is the first word
+    /// (see [`Instruction::is_long`]) and `operand` the second.
+    pub fn decode_long(opcode: u16, operand: u16) -> Self {
+        if Instruction::is_long(opcode) {
+            Instruction::SetILong(operand as MemAddr)
+        } else {
+            Instruction::decode(opcode)
+        }
+    }
+
+    /// Encodes the instruction as an opcode, the inverse of [`Instruction::decode`].
+    ///
+    /// `NoOp` and `Unsupported` pass their opcode through, provided it decodes
+    /// back to the same variant. For `SetILong` only the first word is returned;
+    /// use [`Instruction::encode_words`] to get the operand too.
+    pub fn encode(&self) -> Result<u16, EncodeError> {
+        let xy =
+            |x: RegId, y: RegId| -> Result<u16, EncodeError> { Ok(reg(x)? << 8 | reg(y)? << 4) };
+        let xnn = |x: RegId, nn: u8| -> Result<u16, EncodeError> { Ok(reg(x)? << 8 | nn as u16) };
+        let x = |x: RegId| -> Result<u16, EncodeError> { Ok(reg(x)? << 8) };
+        let opcode = match *self {
+            Instruction::Halt => 0x0000,
+            Instruction::DispClear => 0x00E0,
+            Instruction::Ret => 0x00EE,
+            Instruction::ScrollDown(n) => 0x00C0 | imm(n as usize, 4)?,
+            Instruction::ScrollUp(n) => 0x00D0 | imm(n as usize, 4)?,
+            Instruction::ScrollRight => 0x00FB,
+            Instruction::ScrollLeft => 0x00FC,
+            Instruction::Exit => 0x00FD,
+            Instruction::LoRes => 0x00FE,
+            Instruction::HiRes => 0x00FF,
+            Instruction::Jump(addr) => 0x1000 | imm(addr, 12)?,
+            Instruction::Call(addr) => 0x2000 | imm(addr, 12)?,
+            Instruction::SkipIfEqX(vx, nn) => 0x3000 | xnn(vx, nn)?,
+            Instruction::SkipIfNeX(vx, nn) => 0x4000 | xnn(vx, nn)?,
+            Instruction::SkipIfEqXY(vx, vy) => 0x5000 | xy(vx, vy)?,
+            Instruction::RegDumpRangeIXY(vx, vy) => 0x5002 | xy(vx, vy)?,
+            Instruction::RegLoadRangeIXY(vx, vy) => 0x5003 | xy(vx, vy)?,
+            Instruction::AssignXImm(vx, nn) => 0x6000 | xnn(vx, nn)?,
+            Instruction::AddXImm(vx, nn) => 0x7000 | xnn(vx, nn)?,
+            Instruction::AssignXY(vx, vy) => 0x8000 | xy(vx, vy)?,
+            Instruction::OrXY(vx, vy) => 0x8001 | xy(vx, vy)?,
+            Instruction::AndXY(vx, vy) => 0x8002 | xy(vx, vy)?,
+            Instruction::XorXY(vx, vy) => 0x8003 | xy(vx, vy)?,
+            Instruction::AddXY(vx, vy) => 0x8004 | xy(vx, vy)?,
+            Instruction::SubXY(vx, vy) => 0x8005 | xy(vx, vy)?,
+            Instruction::Shr1X(vx, vy) => 0x8006 | xy(vx, vy)?,
+            Instruction::SubYX(vx, vy) => 0x8007 | xy(vx, vy)?,
+            Instruction::Shl1X(vx, vy) => 0x800E | xy(vx, vy)?,
+            Instruction::SkipIfNeXY(vx, vy) => 0x9000 | xy(vx, vy)?,
+            Instruction::SetI(addr) => 0xA000 | imm(addr, 12)?,
+            Instruction::JumpV0(addr) => 0xB000 | imm(addr, 12)?,
+            Instruction::RandX(vx, nn) => 0xC000 | xnn(vx, nn)?,
+            Instruction::DispDraw(vx, vy, n) => 0xD000 | xy(vx, vy)? | imm(n as usize, 4)?,
+            Instruction::SkipIfKeyEqX(vx) => 0xE09E | x(vx)?,
+            Instruction::SkipIfKeyNeX(vx) => 0xE0A1 | x(vx)?,
+            Instruction::SetILong(addr) => {
+                imm(addr, 16)?;
+                0xF000
+            }
+            Instruction::SelectPlanes(n) => 0xF001 | imm(n as usize, 4)? << 8,
+            Instruction::LoadAudio => 0xF002,
+            Instruction::GetDelayX(vx) => 0xF007 | x(vx)?,
+            Instruction::AwaitKeyX(vx) => 0xF00A | x(vx)?,
+            Instruction::SetDelayX(vx) => 0xF015 | x(vx)?,
+            Instruction::SetSoundX(vx) => 0xF018 | x(vx)?,
+            Instruction::AddIX(vx) => 0xF01E | x(vx)?,
+            Instruction::SpriteAddrIX(vx) => 0xF029 | x(vx)?,
+            Instruction::BigSpriteAddrIX(vx) => 0xF030 | x(vx)?,
+            Instruction::DumpBcdIX(vx) => 0xF033 | x(vx)?,
+            Instruction::SetPitchX(vx) => 0xF03A | x(vx)?,
+            Instruction::RegDumpIX(vx) => 0xF055 | x(vx)?,
+            Instruction::RegLoadIX(vx) => 0xF065 | x(vx)?,
+            Instruction::StoreFlagsX(vx) => 0xF075 | x(vx)?,
+            Instruction::LoadFlagsX(vx) => 0xF085 | x(vx)?,
+            Instruction::NoOp(opcode) => match Instruction::decode(opcode) {
+                Instruction::NoOp(_) => opcode,
+                _ => return Err(EncodeError::NotPassThrough(opcode)),
+            },
+            Instruction::Unsupported(opcode) => match Instruction::decode(opcode) {
+                Instruction::Unsupported(_) => opcode,
+                _ => return Err(EncodeError::NotPassThrough(opcode)),
+            },
+        };
+        Ok(opcode)
+    }
+
+    /// Encodes the instruction as one word, or two for `SetILong`.
+    pub fn encode_words(&self) -> Result<Vec<u16>, EncodeError> {
+        let opcode = self.encode()?;
+        match *self {
+            Instruction::SetILong(addr) => Ok(vec![opcode, addr as u16]),
+            _ => Ok(vec![opcode]),
+        }
+    }
+
+    /// Decodes a single word.
+    ///
+    /// `F000` decodes as `HALT`; use [`Instruction::decode_long`] for XO-CHIP,
+    /// where it is the first word of `LDL I NNNN`.
     pub fn decode(opcode: u16) -> Self {
         let op_cls = (opcode & 0xF000) >> 12;
         match op_cls {
@@ -185,6 +512,20 @@ impl Instruction {
                     Instruction::DispClear
                 } else if opcode == 0x00EE {
                     Instruction::Ret
+                } else if opcode & 0xFFF0 == 0x00C0 {
+                    Instruction::ScrollDown((opcode & 0x000F) as u8)
+                } else if opcode & 0xFFF0 == 0x00D0 {
+                    Instruction::ScrollUp((opcode & 0x000F) as u8)
+                } else if opcode == 0x00FB {
+                    Instruction::ScrollRight
+                } else if opcode == 0x00FC {
+                    Instruction::ScrollLeft
+                } else if opcode == 0x00FD {
+                    Instruction::Exit
+                } else if opcode == 0x00FE {
+                    Instruction::LoRes
+                } else if opcode == 0x00FF {
+                    Instruction::HiRes
                 } else {
                     Instruction::NoOp(opcode)
                 }
@@ -208,6 +549,8 @@ impl Instruction {
                 let op = opcode & 0x000F;
                 match op {
                     0 => Instruction::SkipIfEqXY(x, y),
+                    2 => Instruction::RegDumpRangeIXY(x, y),
+                    3 => Instruction::RegLoadRangeIXY(x, y),
                     _ => Instruction::Unsupported(opcode),
                 }
             }
@@ -222,9 +565,9 @@ impl Instruction {
                     0x3 => Instruction::XorXY(x, y),
                     0x4 => Instruction::AddXY(x, y),
                     0x5 => Instruction::SubXY(x, y),
-                    0x6 => Instruction::Shr1X(x),
+                    0x6 => Instruction::Shr1X(x, y),
                     0x7 => Instruction::SubYX(x, y),
-                    0xE => Instruction::Shl1X(x),
+                    0xE => Instruction::Shl1X(x, y),
                     _ => Instruction::Unsupported(opcode),
                 }
             }
@@ -272,9 +615,15 @@ impl Instruction {
                     0x33 => Instruction::DumpBcdIX(x),
                     0x55 => Instruction::RegDumpIX(x),
                     0x65 => Instruction::RegLoadIX(x),
+                    0x30 => Instruction::BigSpriteAddrIX(x),
+                    0x75 => Instruction::StoreFlagsX(x),
+                    0x85 => Instruction::LoadFlagsX(x),
+                    0x3A => Instruction::SetPitchX(x),
+                    0x01 => Instruction::SelectPlanes(x as u8),
                     0x17 => Instruction::NoOp(opcode),
                     _ => match opcode {
                         0xF000 => Instruction::Halt,
+                        0xF002 => Instruction::LoadAudio,
                         _ => Instruction::Unsupported(opcode),
                     },
                 }
@@ -283,3 +632,112 @@ impl Instruction {
         }
     }
 }
+
+/// Renders the instruction in the mnemonic form documented on each variant,
+/// as accepted by the [`assembler`](crate::assembler).
+///
+/// Opcodes without a mnemonic are rendered as a `dw` data word.
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Instruction::AssignXImm(x, imm) => write!(f, "LD V{:X} {:#04X}", x, imm),
+            Instruction::AddXImm(x, imm) => write!(f, "ADD V{:X} {:#04X}", x, imm),
+            Instruction::AssignXY(x, y) => write!(f, "LD V{:X} V{:X}", x, y),
+            Instruction::OrXY(x, y) => write!(f, "OR V{:X} V{:X}", x, y),
+            Instruction::AndXY(x, y) => write!(f, "AND V{:X} V{:X}", x, y),
+            Instruction::XorXY(x, y) => write!(f, "XOR V{:X} V{:X}", x, y),
+            Instruction::AddXY(x, y) => write!(f, "ADD V{:X} V{:X}", x, y),
+            Instruction::SubXY(x, y) => write!(f, "SUB V{:X} V{:X}", x, y),
+            Instruction::Shr1X(x, y) => write!(f, "SHR V{:X} V{:X}", x, y),
+            Instruction::SubYX(x, y) => write!(f, "SUBN V{:X} V{:X}", x, y),
+            Instruction::Shl1X(x, y) => write!(f, "SHL V{:X} V{:X}", x, y),
+            Instruction::DispClear => write!(f, "CLS"),
+            Instruction::DispDraw(x, y, n) => write!(f, "DRW V{:X} V{:X} {}", x, y, n),
+            Instruction::ScrollDown(n) => write!(f, "SCD {}", n),
+            Instruction::ScrollUp(n) => write!(f, "SCU {}", n),
+            Instruction::ScrollRight => write!(f, "SCR"),
+            Instruction::ScrollLeft => write!(f, "SCL"),
+            Instruction::LoRes => write!(f, "LOW"),
+            Instruction::HiRes => write!(f, "HIGH"),
+            Instruction::SelectPlanes(planes) => write!(f, "PLANE {}", planes),
+            Instruction::SkipIfEqX(x, imm) => write!(f, "SE V{:X} {:#04X}", x, imm),
+            Instruction::SkipIfNeX(x, imm) => write!(f, "SNE V{:X} {:#04X}", x, imm),
+            Instruction::SkipIfEqXY(x, y) => write!(f, "SE V{:X} V{:X}", x, y),
+            Instruction::SkipIfNeXY(x, y) => write!(f, "SNE V{:X} V{:X}", x, y),
+            Instruction::Jump(addr) => write!(f, "JP {:#05X}", addr),
+            Instruction::JumpV0(addr) => write!(f, "JPV0 {:#05X}", addr),
+            Instruction::Call(addr) => write!(f, "CALL {:#05X}", addr),
+            Instruction::Ret => write!(f, "RET"),
+            Instruction::NoOp(opcode) if opcode & 0xF000 == 0 => write!(f, "SYS {:#05X}", opcode),
+            Instruction::SkipIfKeyEqX(x) => write!(f, "SKP V{:X}", x),
+            Instruction::SkipIfKeyNeX(x) => write!(f, "SKNP V{:X}", x),
+            Instruction::GetDelayX(x) => write!(f, "LDDT V{:X}", x),
+            Instruction::AwaitKeyX(x) => write!(f, "LDK V{:X}", x),
+            Instruction::SetDelayX(x) => write!(f, "STDT V{:X}", x),
+            Instruction::SetSoundX(x) => write!(f, "STST V{:X}", x),
+            Instruction::SetI(addr) => write!(f, "LD I {:#05X}", addr),
+            Instruction::SetILong(addr) => write!(f, "LDL I {:#06X}", addr),
+            Instruction::AddIX(x) => write!(f, "ADDI V{:X}", x),
+            Instruction::SpriteAddrIX(x) => write!(f, "LDSPR V{:X}", x),
+            Instruction::BigSpriteAddrIX(x) => write!(f, "LDHSPR V{:X}", x),
+            Instruction::DumpBcdIX(x) => write!(f, "STBCD V{:X}", x),
+            Instruction::RegDumpIX(x) => write!(f, "STREGS V{:X}", x),
+            Instruction::RegLoadIX(x) => write!(f, "LDREGS V{:X}", x),
+            Instruction::RegDumpRangeIXY(x, y) => write!(f, "SAVE V{:X} V{:X}", x, y),
+            Instruction::RegLoadRangeIXY(x, y) => write!(f, "LOAD V{:X} V{:X}", x, y),
+            Instruction::StoreFlagsX(x) => write!(f, "STRPL V{:X}", x),
+            Instruction::LoadFlagsX(x) => write!(f, "LDRPL V{:X}", x),
+            Instruction::LoadAudio => write!(f, "AUDIO"),
+            Instruction::SetPitchX(x) => write!(f, "PITCH V{:X}", x),
+            Instruction::RandX(x, imm) => write!(f, "RND V{:X} {:#04X}", x, imm),
+            Instruction::Halt => write!(f, "HALT"),
+            Instruction::Exit => write!(f, "EXIT"),
+            Instruction::NoOp(opcode) | Instruction::Unsupported(opcode) => {
+                write!(f, "dw {:#06X}", opcode)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_inverts_decode() {
+        for opcode in 0..=u16::MAX {
+            let instr = Instruction::decode(opcode);
+            if Instruction::is_long(opcode) {
+                // outside of XO-CHIP, a second encoding of `HALT`
+                assert!(matches!(instr, Instruction::Halt));
+                continue;
+            }
+            assert_eq!(instr.encode(), Ok(opcode), "{:04X} ({:?})", opcode, instr);
+        }
+        let long = Instruction::decode_long(0xF000, 0xBEEF);
+        assert_eq!(long.encode_words(), Ok(vec![0xF000, 0xBEEF]));
+    }
+
+    #[test]
+    fn encode_rejects_invalid_fields() {
+        assert_eq!(
+            Instruction::AssignXImm(16, 0).encode(),
+            Err(EncodeError::InvalidRegister(16))
+        );
+        assert_eq!(
+            Instruction::Jump(0x1000).encode(),
+            Err(EncodeError::OutOfRange {
+                value: 0x1000,
+                bits: 12
+            })
+        );
+        assert_eq!(
+            Instruction::DispDraw(0, 0, 16).encode(),
+            Err(EncodeError::OutOfRange { value: 16, bits: 4 })
+        );
+        assert_eq!(
+            Instruction::NoOp(0x00E0).encode(),
+            Err(EncodeError::NotPassThrough(0x00E0))
+        );
+    }
+}
@@ -1,27 +1,220 @@
+//! The 16-key hexadecimal keypad and its mapping to host keys.
+//!
+//! The keys are laid out like on the COSMAC VIP:
+//!
+//! ```text
+//! 1 2 3 C
+//! 4 5 6 D
+//! 7 8 9 E
+//! A 0 B F
+//! ```
+
+use thiserror::Error;
+
+/// Number of keys on the keypad
+pub const KEY_COUNT: usize = 16;
+
+/// CHIP-8 keys in keypad order, row by row
+const KEYPAD_ORDER: [u8; KEY_COUNT] = [
+    0x1, 0x2, 0x3, 0xC, 0x4, 0x5, 0x6, 0xD, 0x7, 0x8, 0x9, 0xE, 0xA, 0x0, 0xB, 0xF,
+];
+
+/// Default host keys, in keypad order
+pub const DEFAULT_LAYOUT: &str = "1234qwerasdfzxcv";
+
+/// Progress of `LDK VX` towards getting a key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum KeyWait {
+    Idle,
+    Pressed(u8),
+    /// pressed and then released
+    Released(u8),
+}
+
 pub struct KeyBoard {
-    pressed: Option<u8>,
+    /// bit `k` is set while key `k` is held down
+    keys: u16,
+    wait: KeyWait,
+}
+
+impl Default for KeyBoard {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl KeyBoard {
     pub fn new() -> Self {
         Self {
-            pressed: Some(b'q'),
+            keys: 0,
+            wait: KeyWait::Idle,
         }
     }
 
-    pub fn press(&mut self, key: u8) {
-        self.pressed = Some(key);
+    /// Presses key `key & 0xF`.
+    pub fn key_down(&mut self, key: u8) {
+        let key = key & 0xF;
+        self.keys |= 1 << key;
+        if self.wait == KeyWait::Idle {
+            self.wait = KeyWait::Pressed(key);
+        }
     }
 
-    pub(crate) fn await_key_press(&mut self) -> u8 {
-        loop {
-            if let Some(key) = self.pressed.take() {
-                return key;
+    /// Releases key `key & 0xF`.
+    pub fn key_up(&mut self, key: u8) {
+        let key = key & 0xF;
+        self.keys &= !(1 << key);
+        if self.wait == KeyWait::Pressed(key) {
+            self.wait = KeyWait::Released(key);
+        }
+    }
+
+    /// Returns `true` while key `key & 0xF` is held down.
+    pub fn is_down(&self, key: u8) -> bool {
+        self.keys & (1 << (key & 0xF)) != 0
+    }
+
+    /// The keys held down, bit `k` for key `k`.
+    pub fn keys(&self) -> u16 {
+        self.keys
+    }
+
+    pub(crate) fn wait(&self) -> KeyWait {
+        self.wait
+    }
+
+    pub(crate) fn restore(&mut self, keys: u16, wait: KeyWait) {
+        self.keys = keys;
+        self.wait = wait;
+    }
+
+    /// Starts waiting for a key. Waiting for a release takes the lowest key
+    /// already held down, if any, like the COSMAC VIP; waiting for a press
+    /// needs a new one.
+    pub(crate) fn start_wait(&mut self, on_press: bool) {
+        self.wait = match self.keys {
+            keys if keys != 0 && !on_press => KeyWait::Pressed(keys.trailing_zeros() as u8),
+            _ => KeyWait::Idle,
+        };
+    }
+
+    /// Returns the key waited for once it has been released, or as soon as
+    /// it is pressed if `on_press` is set.
+    pub(crate) fn take_key(&mut self, on_press: bool) -> Option<u8> {
+        match self.wait {
+            KeyWait::Pressed(key) if on_press => {
+                self.wait = KeyWait::Idle;
+                Some(key)
+            }
+            KeyWait::Released(key) => {
+                self.wait = KeyWait::Idle;
+                Some(key)
             }
+            _ => None,
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum KeyMapError {
+    #[error("a key layout needs {KEY_COUNT} keys, got {0}")]
+    WrongLength(usize),
+    #[error("host key '{0}' is used twice")]
+    Duplicate(char),
+}
+
+/// Mapping of host keys to keypad keys.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyMap {
+    /// host key of each keypad key, in keypad order; letters in lowercase
+    host: [char; KEY_COUNT],
+}
+
+impl Default for KeyMap {
+    fn default() -> Self {
+        Self::from_layout(DEFAULT_LAYOUT).unwrap()
+    }
+}
+
+impl KeyMap {
+    /// Creates a mapping from 16 host keys given in keypad order, e.g.
+    /// [`DEFAULT_LAYOUT`]. Letters match regardless of case.
+    pub fn from_layout(layout: &str) -> Result<Self, KeyMapError> {
+        let keys: Vec<char> = layout.chars().map(|c| c.to_ascii_lowercase()).collect();
+        let host: [char; KEY_COUNT] = keys
+            .as_slice()
+            .try_into()
+            .map_err(|_| KeyMapError::WrongLength(keys.len()))?;
+        for (n, c) in host.iter().enumerate() {
+            if host[..n].contains(c) {
+                return Err(KeyMapError::Duplicate(*c));
+            }
+        }
+        Ok(Self { host })
+    }
+
+    /// The keypad key mapped to a host key.
+    pub fn key(&self, host: char) -> Option<u8> {
+        let host = host.to_ascii_lowercase();
+        let pos = self.host.iter().position(|c| *c == host)?;
+        Some(KEYPAD_ORDER[pos])
+    }
+
+    /// The host key mapped to keypad key `key & 0xF`.
+    pub fn host_key(&self, key: u8) -> char {
+        let pos = KEYPAD_ORDER.iter().position(|k| *k == key & 0xF).unwrap();
+        self.host[pos]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_are_held_until_released() {
+        let mut keyboard = KeyBoard::new();
+        keyboard.key_down(0x3);
+        keyboard.key_down(0x1C);
+        assert!(keyboard.is_down(0x3) && keyboard.is_down(0xC));
+        assert_eq!(keyboard.keys(), 0b0001_0000_0000_1000);
+        // only the first press is waiting for `LDK VX`
+        assert_eq!(keyboard.take_key(true), Some(0x3));
+        assert_eq!(keyboard.take_key(true), None);
+
+        keyboard.key_up(0x3);
+        assert!(!keyboard.is_down(0x3));
+        assert!(keyboard.is_down(0xC));
+        keyboard.key_up(0xC);
+        assert_eq!(keyboard.keys(), 0);
+    }
+
+    #[test]
+    fn default_layout() {
+        let map = KeyMap::default();
+        assert_eq!(map.key('1'), Some(0x1));
+        assert_eq!(map.key('4'), Some(0xC));
+        assert_eq!(map.key('X'), Some(0x0));
+        assert_eq!(map.key('v'), Some(0xF));
+        assert_eq!(map.key('p'), None);
+        for key in 0..KEY_COUNT as u8 {
+            assert_eq!(map.key(map.host_key(key)), Some(key));
         }
     }
 
-    pub(crate) fn get_key_pressed(&self) -> u8 {
-        self.pressed.unwrap_or(b'\0')
+    #[test]
+    fn invalid_layouts_are_rejected() {
+        assert!(matches!(
+            KeyMap::from_layout("1234qwerasdfzxc"),
+            Err(KeyMapError::WrongLength(15))
+        ));
+        assert!(matches!(
+            KeyMap::from_layout("1234qwerasdfzxcvb"),
+            Err(KeyMapError::WrongLength(17))
+        ));
+        assert!(matches!(
+            KeyMap::from_layout("1234qwerasdfzxcQ"),
+            Err(KeyMapError::Duplicate('q'))
+        ));
     }
 }
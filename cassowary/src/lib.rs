@@ -1,25 +1,100 @@
+pub mod assembler;
+pub mod audio;
+pub mod capture;
 mod cpu;
+pub mod debugger;
+pub mod disassembler;
 mod display;
 mod instructions;
 mod keyboard;
 mod memory;
+pub mod movie;
 pub mod progloader;
+pub mod quirks;
+pub mod random;
+pub mod render;
+pub mod rewind;
+pub mod scheduler;
 mod sound;
+pub mod state;
 mod timer;
+pub mod trace;
 
-pub use crate::cpu::{Cpu, CpuError};
+pub use crate::cpu::{Cpu, CpuError, Step};
 pub use crate::display::Display;
-pub use crate::keyboard::KeyBoard;
-pub use crate::memory::{Memory, MemoryError};
-pub use crate::sound::{SoundError, SoundSystem};
-pub use crate::timer::DelayTimer;
+pub use crate::instructions::{EncodeError, Instruction, InstructionClass, MemAddr, RegId};
+pub use crate::keyboard::{KeyBoard, KeyMap, KeyMapError, DEFAULT_LAYOUT, KEY_COUNT};
+pub use crate::memory::{
+    Access, Memory, MemoryError, WatchAction, WatchEvent, Watchpoint, MEMORY_SIZE, XO_MEMORY_SIZE,
+};
+pub use crate::quirks::Quirks;
+pub use crate::random::{RandomSource, SeededRandom};
+pub use crate::rewind::RewindError;
+pub use crate::sound::{AudioPattern, SoundError, SoundSystem};
+pub use crate::state::StateError;
+pub use crate::timer::{DelayTimer, TimerMode};
 
-use thiserror::Error;
+use crate::audio::AudioBackend;
+use crate::movie::{KeyEvent, Movie, MovieError};
+use crate::render::{NullRenderer, Renderer};
+use crate::rewind::{KeyChange, Mark, Rewind};
+use crate::state::SystemState;
+use crate::trace::{registers_used, TraceEntry, Tracer};
 
-#[derive(Error, Debug)]
-pub enum SystemError {
-    #[error("sound system error: {0}")]
-    SoundError(#[from] SoundError),
+/// Default number of instructions executed per 60 Hz frame.
+pub const INSTRUCTIONS_PER_FRAME: usize = 10;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Config {
+    pub timer_mode: TimerMode,
+    /// Number of instructions that make up one 60 Hz frame.
+    /// Emulated timers are decremented once per frame.
+    pub instructions_per_frame: usize,
+    /// Seed of the default random source used by `RND VX NN`.
+    pub seed: u64,
+    pub quirks: Quirks,
+    /// Size of the memory in bytes: [`MEMORY_SIZE`], or [`XO_MEMORY_SIZE`] for XO-CHIP
+    pub memory_size: usize,
+    /// Memory budget of the [`rewind`] history in bytes, `0` to disable it
+    pub rewind_budget: usize,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            timer_mode: TimerMode::Emulated,
+            instructions_per_frame: INSTRUCTIONS_PER_FRAME,
+            seed: random::DEFAULT_SEED,
+            quirks: Quirks::default(),
+            memory_size: MEMORY_SIZE,
+            rewind_budget: 0,
+        }
+    }
+}
+
+/// Summary of a run of several instructions.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RunSummary {
+    /// Number of instructions executed
+    pub executed: usize,
+    /// The last executed step, if any
+    pub last: Option<Step>,
+    /// `true` if any of the instructions changed the display
+    pub display_changed: bool,
+    /// `true` if the run stopped because the CPU is waiting for a key press
+    pub awaiting_key: bool,
+    /// `true` if the run stopped because the CPU halted
+    pub halted: bool,
+}
+
+impl RunSummary {
+    fn record(&mut self, step: Step) {
+        self.executed += 1;
+        self.last = Some(step);
+        self.display_changed |= step.display_changed;
+        self.awaiting_key = step.awaiting_key;
+        self.halted = step.halted;
+    }
 }
 
 pub struct System {
@@ -29,33 +104,446 @@ pub struct System {
     sound: SoundSystem,
     display: Display,
     keyboard: KeyBoard,
+    config: Config,
+    /// instructions executed in the current frame
+    frame_cycles: usize,
+    frame: u64,
+    /// instructions executed so far
+    cycles: u64,
+    tracer: Option<Tracer>,
+    rewind: Option<Rewind>,
+    recording: Option<Movie>,
+    /// movie being replayed and the index of its next event
+    playback: Option<(Movie, usize)>,
+    renderer: Box<dyn Renderer>,
+    /// whether the display changed since it was last presented
+    display_changed: bool,
+}
+
+impl Default for System {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl System {
-    pub fn new() -> Result<Self, SystemError> {
-        let sound = SoundSystem::start_new()?;
-        let delay = DelayTimer::start_new();
-        Ok(Self {
-            cpu: Cpu::new(),
-            mem: Memory::new(),
+    pub fn new() -> Self {
+        Self::with_config(Config::default())
+    }
+
+    /// Creates a system with the given configuration. It plays no sound and
+    /// shows nothing until given an [`audio`] backend and a [`render`]er.
+    pub fn with_config(config: Config) -> Self {
+        let sound = SoundSystem::new(config.timer_mode);
+        let delay = DelayTimer::new(config.timer_mode);
+        let mut cpu = Cpu::new();
+        cpu.set_random_source(Box::new(SeededRandom::new(config.seed)));
+        cpu.set_quirks(config.quirks);
+        Self {
+            cpu,
+            mem: Memory::with_size(config.memory_size),
             delay,
             sound,
             display: Display::new(),
             keyboard: KeyBoard::new(),
-        })
+            config,
+            frame_cycles: 0,
+            frame: 0,
+            cycles: 0,
+            tracer: None,
+            rewind: (config.rewind_budget > 0).then(|| Rewind::new(config.rewind_budget)),
+            recording: None,
+            playback: None,
+            renderer: Box::new(NullRenderer),
+            display_changed: false,
+        }
     }
 
+    /// Runs frame after frame, as fast as the host allows, until the CPU
+    /// halts. A [`scheduler`] runs them in real time.
     pub fn run(&mut self) -> Result<(), CpuError> {
-        self.cpu.run(
+        while !self.run_frame()?.halted {}
+        Ok(())
+    }
+
+    /// Executes a single instruction.
+    ///
+    /// Every [`Config::instructions_per_frame`] instructions complete a frame,
+    /// which decrements the emulated timers.
+    pub fn step(&mut self) -> Result<Step, CpuError> {
+        self.start_rewind();
+        self.play_events();
+        self.execute(true)
+    }
+
+    /// Executes a single instruction. Unless `live`, i.e. while replaying up
+    /// to a rewind target, nothing is traced, presented, played or recorded.
+    fn execute(&mut self, live: bool) -> Result<Step, CpuError> {
+        let executing = !self.cpu.is_halted();
+        let registers = *self.cpu.registers();
+        let step = self.cpu.step(
             &mut self.mem,
             &mut self.delay,
             &mut self.display,
             &mut self.keyboard,
             &mut self.sound,
-        )
+        )?;
+        if executing {
+            let memory = self.mem.take_writes();
+            if live {
+                self.trace(&step, &registers, memory);
+            }
+            self.cycles += 1;
+        }
+        self.display_changed |= step.display_changed;
+        if step.halted {
+            if executing && live {
+                self.present();
+            }
+        } else {
+            self.frame_cycles += 1;
+            let vblank = self.config.quirks.display_wait
+                && matches!(step.instruction, Instruction::DispDraw(..));
+            if vblank || self.frame_cycles >= self.config.instructions_per_frame {
+                self.end_frame(live);
+            }
+        }
+        Ok(step)
+    }
+
+    /// Executes up to `n` instructions, stopping early if the CPU halts or
+    /// starts waiting for a key press.
+    pub fn step_n(&mut self, n: usize) -> Result<RunSummary, CpuError> {
+        let mut summary = RunSummary::default();
+        for _ in 0..n {
+            summary.record(self.step()?);
+            if summary.halted || summary.awaiting_key {
+                break;
+            }
+        }
+        Ok(summary)
+    }
+
+    /// Executes instructions up to the end of the current 60 Hz frame,
+    /// stopping early only if the CPU halts.
+    ///
+    /// Waiting for a key press does not end the frame: the timers keep
+    /// running while the CPU waits.
+    pub fn run_frame(&mut self) -> Result<RunSummary, CpuError> {
+        let mut summary = RunSummary::default();
+        let frame = self.frame;
+        while self.frame == frame {
+            summary.record(self.step()?);
+            if summary.halted {
+                break;
+            }
+        }
+        Ok(summary)
+    }
+
+    fn trace(&mut self, step: &Step, registers_before: &[u8; 16], memory: Vec<(MemAddr, u8)>) {
+        let Some(tracer) = &mut self.tracer else {
+            return;
+        };
+        if !tracer.accepts(step) {
+            return;
+        }
+        let (read, written) = registers_used(step, self.cpu.quirks());
+        let registers_after = self.cpu.registers();
+        tracer.record(&TraceEntry {
+            cycle: self.cycles,
+            step: *step,
+            read: read
+                .into_iter()
+                .map(|reg| (reg, registers_before[reg]))
+                .collect(),
+            written: written
+                .into_iter()
+                .map(|reg| (reg, registers_after[reg]))
+                .collect(),
+            index: self.cpu.index(),
+            memory,
+        });
+    }
+
+    /// Starts writing every executed instruction to `tracer`, replacing
+    /// the previous tracer (which is returned).
+    pub fn set_tracer(&mut self, tracer: Tracer) -> Option<Tracer> {
+        self.mem.record_writes(true);
+        self.tracer.replace(tracer)
+    }
+
+    /// Stops tracing, returning the tracer.
+    pub fn take_tracer(&mut self) -> Option<Tracer> {
+        self.mem.record_writes(false);
+        self.tracer.take()
+    }
+
+    /// Saves the complete machine state in the format documented in [`state`].
+    ///
+    /// The configuration, tracer and watchpoints are not part of the state.
+    pub fn save_state(&self) -> Vec<u8> {
+        SystemState {
+            cycles: self.cycles,
+            frame: self.frame,
+            frame_cycles: self.frame_cycles,
+            cpu: self.cpu.state(),
+            delay: self.delay.get(),
+            sound: self.sound.get_timer(),
+            pitch: self.sound.pitch(),
+            pattern: self.sound.pattern().map(|pattern| pattern.bits),
+            keys: self.keyboard.keys(),
+            key_wait: self.keyboard.wait(),
+            display: self.display.state(),
+            memory: self.mem.bytes().to_vec(),
+        }
+        .encode()
+    }
+
+    /// Restores a state saved by [`System::save_state`].
+    ///
+    /// The state is validated before anything is restored, so on error the
+    /// system is left unchanged.
+    ///
+    /// This starts a new [`rewind`] history.
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), StateError> {
+        self.restore_state(state)?;
+        if let Some(rewind) = &self.rewind {
+            self.rewind = Some(Rewind::new(rewind.budget()));
+        }
+        Ok(())
+    }
+
+    fn restore_state(&mut self, state: &[u8]) -> Result<(), StateError> {
+        let state = SystemState::decode(state, self.mem.size())?;
+        self.cycles = state.cycles;
+        self.frame = state.frame;
+        self.frame_cycles = state.frame_cycles;
+        self.cpu.restore(&state.cpu);
+        self.delay.set(state.delay);
+        self.sound.set_timer(state.sound);
+        self.sound.restore_audio(state.pitch, state.pattern);
+        self.keyboard.restore(state.keys, state.key_wait);
+        self.display.restore(&state.display);
+        self.mem.bytes_mut().copy_from_slice(&state.memory);
+        Ok(())
+    }
+
+    /// Number of instructions executed so far.
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// Presses keypad key `key & 0xF`, recording it if a movie is being recorded.
+    pub fn key_down(&mut self, key: u8) {
+        self.record_key(key & 0xF, true);
+        self.set_key(key & 0xF, true);
+    }
+
+    /// Releases keypad key `key & 0xF`, recording it if a movie is being recorded.
+    pub fn key_up(&mut self, key: u8) {
+        self.record_key(key & 0xF, false);
+        self.set_key(key & 0xF, false);
+    }
+
+    /// Presses or releases a key, keeping the change in the rewind history.
+    fn set_key(&mut self, key: u8, down: bool) {
+        self.start_rewind();
+        if let Some(rewind) = &mut self.rewind {
+            rewind.push_key(KeyChange {
+                cycles: self.cycles,
+                key,
+                down,
+            });
+        }
+        self.press(key, down);
+    }
+
+    fn press(&mut self, key: u8, down: bool) {
+        if down {
+            self.keyboard.key_down(key);
+        } else {
+            self.keyboard.key_up(key);
+        }
+    }
+
+    fn record_key(&mut self, key: u8, down: bool) {
+        if let Some(movie) = &mut self.recording {
+            movie.events.push(KeyEvent {
+                frame: self.frame,
+                offset: self.frame_cycles,
+                key,
+                down,
+            });
+        }
+    }
+
+    /// Starts recording a [`movie`] from the current state.
+    pub fn start_recording(&mut self) {
+        self.recording = Some(Movie::new(&self.config, self.save_state()));
+    }
+
+    /// Stops recording, returning the movie.
+    pub fn stop_recording(&mut self) -> Option<Movie> {
+        self.recording.take()
+    }
+
+    /// Restores the movie's starting state and replays its key changes
+    /// as execution continues.
+    pub fn play_movie(&mut self, movie: Movie) -> Result<(), MovieError> {
+        movie.check_config(&self.config)?;
+        self.load_state(&movie.start)?;
+        self.playback = Some((movie, 0));
+        Ok(())
+    }
+
+    /// Returns `true` while the replayed movie has key changes left.
+    pub fn is_playing_movie(&self) -> bool {
+        self.playback.is_some()
+    }
+
+    fn play_events(&mut self) {
+        let Some((movie, next)) = &mut self.playback else {
+            return;
+        };
+        let now = (self.frame, self.frame_cycles);
+        let mut due = Vec::new();
+        let mut finished = true;
+        while let Some(event) = movie.events.get(*next) {
+            if (event.frame, event.offset) > now {
+                finished = false;
+                break;
+            }
+            due.push(*event);
+            *next += 1;
+        }
+        if finished {
+            self.playback = None;
+        }
+        // replayed keys are recorded too, so a movie can be extended
+        for event in due {
+            self.record_key(event.key, event.down);
+            self.set_key(event.key, event.down);
+        }
+    }
+
+    /// Number of frames completed so far.
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    fn end_frame(&mut self, live: bool) {
+        self.frame_cycles = 0;
+        self.frame += 1;
+        self.delay.tick();
+        if live {
+            self.sound.tick();
+            self.present();
+            self.record_rewind();
+        } else {
+            self.sound.skip_frame();
+        }
+    }
+
+    /// Records the first snapshot of an empty rewind history.
+    fn start_rewind(&mut self) {
+        if self.rewind.as_ref().is_some_and(Rewind::is_empty) {
+            self.record_rewind();
+        }
+    }
+
+    fn present(&mut self) {
+        self.renderer
+            .present(&self.display, std::mem::take(&mut self.display_changed));
+    }
+
+    /// Replaces the [`audio`] backend the buzzer is played on, returning the
+    /// previous one. The default plays nothing.
+    pub fn set_audio_backend(&mut self, backend: Box<dyn AudioBackend>) -> Box<dyn AudioBackend> {
+        self.sound.set_backend(backend)
+    }
+
+    /// Replaces the [`render`]er the display is presented to at the end of
+    /// every frame, returning the previous one. The default shows nothing.
+    pub fn set_renderer(&mut self, renderer: Box<dyn Renderer>) -> Box<dyn Renderer> {
+        std::mem::replace(&mut self.renderer, renderer)
+    }
+
+    fn record_rewind(&mut self) {
+        if self.rewind.is_none() {
+            return;
+        }
+        let mark = Mark {
+            cycles: self.cycles,
+            frame: self.frame,
+        };
+        let state = self.save_state();
+        if let Some(rewind) = &mut self.rewind {
+            rewind.push(mark, state);
+        }
+    }
+
+    /// Starts keeping a rewind history of at most about `budget` bytes,
+    /// replacing the current history.
+    pub fn enable_rewind(&mut self, budget: usize) {
+        self.rewind = Some(Rewind::new(budget));
+    }
+
+    /// The rewind history, if enabled.
+    pub fn rewind(&self) -> Option<&Rewind> {
+        self.rewind.as_ref()
+    }
+
+    /// Goes back `n` instructions.
+    pub fn rewind_instructions(&mut self, n: u64) -> Result<(), RewindError> {
+        self.rewind_to(self.cycles.saturating_sub(n))
+    }
+
+    /// Goes back to the start of the frame `n` frames before the current one
+    /// (`0` being the start of the current frame).
+    pub fn rewind_frames(&mut self, n: u64) -> Result<(), RewindError> {
+        let rewind = self.rewind.as_ref().ok_or(RewindError::NotEnabled)?;
+        let frame = self.frame.saturating_sub(n);
+        let cycles = rewind.frame_start(frame).ok_or(RewindError::TooFar {
+            oldest: rewind.oldest().unwrap_or(self.cycles),
+        })?;
+        self.rewind_to(cycles)
     }
 
-    pub fn memory(&mut self) -> &Memory {
+    fn rewind_to(&mut self, cycles: u64) -> Result<(), RewindError> {
+        let rewind = self.rewind.as_mut().ok_or(RewindError::NotEnabled)?;
+        match rewind.oldest() {
+            Some(oldest) if oldest <= cycles => {}
+            oldest => {
+                return Err(RewindError::TooFar {
+                    oldest: oldest.unwrap_or(self.cycles),
+                })
+            }
+        }
+        let (_, state, keys) = rewind.back_to(cycles).expect("snapshot before oldest");
+        let (state, keys) = (state.to_vec(), keys.to_vec());
+        self.restore_state(&state)?;
+        let mut keys = keys.into_iter().peekable();
+        while self.cycles < cycles && !self.cpu.is_halted() {
+            while let Some(change) = keys.next_if(|change| change.cycles <= self.cycles) {
+                self.press(change.key, change.down);
+            }
+            self.execute(false)?;
+        }
+        Ok(())
+    }
+
+    /// Replaces the source of random bytes used by `RND VX NN`,
+    /// e.g. to record or replay a sequence (see [`random`]).
+    pub fn set_random_source(&mut self, rng: Box<dyn RandomSource>) {
+        self.cpu.set_random_source(rng);
+    }
+
+    pub fn memory(&self) -> &Memory {
         &self.mem
     }
 
@@ -66,4 +554,327 @@ impl System {
     pub fn cpu(&self) -> &Cpu {
         &self.cpu
     }
+
+    pub fn delay_timer(&self) -> u8 {
+        self.delay.get()
+    }
+
+    pub fn sound_timer(&self) -> u8 {
+        self.sound.get_timer()
+    }
+
+    pub fn keyboard(&self) -> &KeyBoard {
+        &self.keyboard
+    }
+
+    pub fn display(&self) -> &Display {
+        &self.display
+    }
+
+    pub fn display_mut(&mut self) -> &mut Display {
+        &mut self.display
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A system with the firmware and `program` (hex words, as read by the
+    /// program loader) loaded at 0x200, about to execute the program.
+    pub(crate) fn system(program: &str) -> System {
+        system_with(Config::default(), program)
+    }
+
+    pub(crate) fn system_with(config: Config, program: &str) -> System {
+        booted(config, |mem| {
+            progloader::load_from_hex(&format!("0200 {}", program), mem).unwrap()
+        })
+    }
+
+    /// Like [`system`], but with the program assembled from `source`.
+    pub(crate) fn assembled(source: &str) -> System {
+        assembled_with(Config::default(), source)
+    }
+
+    pub(crate) fn assembled_with(config: Config, source: &str) -> System {
+        booted(config, |mem| {
+            assembler::assemble(source).unwrap().load(mem).unwrap()
+        })
+    }
+
+    fn booted(config: Config, load: impl FnOnce(&mut Memory)) -> System {
+        let mut system = System::with_config(config);
+        progloader::load_firmware(&mut system.mem).unwrap();
+        load(&mut system.mem);
+        // the firmware's jump to the program
+        system.step().unwrap();
+        system
+    }
+
+    #[test]
+    fn step_executes_one_instruction() {
+        // LD V0 5; LD V1 6
+        let mut system = system("6005 6106");
+        let step = system.step().unwrap();
+        assert!(matches!(step.instruction, Instruction::AssignXImm(0, 5)));
+        assert_eq!((step.pc_before, step.pc_after), (0x200, 0x202));
+        assert_eq!(system.cpu().get_register(0), 5);
+        assert_eq!(system.cpu().get_register(1), 0);
+    }
+
+    #[test]
+    fn halted_cpu_executes_nothing() {
+        // HALT
+        let mut system = system("0000");
+        assert!(system.step().unwrap().halted);
+        let pc = system.cpu().pc();
+        for _ in 0..3 {
+            let step = system.step().unwrap();
+            assert!(step.halted);
+            assert_eq!((step.pc_before, step.pc_after), (pc, pc));
+        }
+        assert!(system.run_frame().unwrap().halted);
+    }
+
+    #[test]
+    fn failed_step_leaves_the_pc() {
+        // LD V0 1; RET
+        let mut system = system("6001 00EE");
+        system.step().unwrap();
+        for _ in 0..2 {
+            assert!(matches!(system.step(), Err(CpuError::StackUnderflow)));
+            assert_eq!(system.cpu().pc(), 0x202);
+            assert_eq!(system.cpu().get_register(0), 1);
+        }
+    }
+
+    #[test]
+    fn failed_register_load_changes_no_register() {
+        // LD V0 7; LD I 0xFFE; LDREGS V2
+        let mut system = system("6007 AFFE F265");
+        system.step_n(2).unwrap();
+        assert!(matches!(
+            system.step(),
+            Err(CpuError::MemoryError(MemoryError::OutOfBounds))
+        ));
+        assert_eq!(system.cpu().get_register(0), 7);
+    }
+
+    #[test]
+    fn failed_register_dump_changes_no_memory() {
+        // LD V0 7; LD I 0xFFE; STREGS V2
+        let mut system = system("6007 AFFE F255");
+        system.step_n(2).unwrap();
+        assert!(system.step().is_err());
+        assert_eq!(system.memory().load_byte(0xFFE).unwrap(), 0);
+        assert_eq!(system.memory().load_byte(0xFFF).unwrap(), 0);
+    }
+
+    #[test]
+    fn delay_timer_ticks_once_per_frame() {
+        // LD V0 3; STDT V0; loop: JP loop
+        let mut system = system("6003 F015 1204");
+        system.step_n(2).unwrap();
+        assert_eq!(system.delay.get(), 3);
+        for expected in [2, 1, 0, 0] {
+            system.run_frame().unwrap();
+            assert_eq!(system.delay.get(), expected);
+        }
+    }
+
+    #[test]
+    fn drawing_xors_and_reports_collisions() {
+        // LD I dot; DRW V0 V0 1; DRW V0 V0 1; HALT; dot: db 0x80
+        let mut system = system("A208 D001 D001 0000 8000");
+        system.step_n(2).unwrap();
+        assert_eq!(system.display.pixel(0, 0), 1);
+        assert_eq!(system.cpu().get_register(0xF), 0);
+        system.step().unwrap();
+        assert_eq!(system.display.pixel(0, 0), 0);
+        assert_eq!(system.cpu().get_register(0xF), 1);
+    }
+
+    #[test]
+    fn resolution_switch() {
+        // HIGH; LOW
+        let mut system = system("00FF 00FE");
+        system.step().unwrap();
+        assert!(system.display.is_hires());
+        assert_eq!((system.display.width(), system.display.height()), (128, 64));
+        system.step().unwrap();
+        assert!(!system.display.is_hires());
+        assert_eq!((system.display.width(), system.display.height()), (64, 32));
+    }
+
+    #[test]
+    fn scrolling() {
+        // LD I dot; DRW V0 V0 1; SCD 3; SCR; SCL; dot: db 0x80
+        let mut system = system("A20A D001 00C3 00FB 00FC 8000");
+        system.step_n(3).unwrap();
+        assert_eq!(system.display.pixel(0, 0), 0);
+        assert_eq!(system.display.pixel(0, 3), 1);
+        system.step().unwrap();
+        assert_eq!(system.display.pixel(0, 3), 0);
+        assert_eq!(system.display.pixel(4, 3), 1);
+        system.step().unwrap();
+        assert_eq!(system.display.pixel(0, 3), 1);
+        assert_eq!(system.display.pixel(4, 3), 0);
+    }
+
+    #[test]
+    fn big_sprite_drawing() {
+        // HIGH; LD I square; DRW V0 V0 0; HALT; square: 32 x db 0xFF
+        let program = format!("00FF A208 D000 0000 {}", "FF".repeat(32));
+        let mut system = system(&program);
+        system.step_n(3).unwrap();
+        assert_eq!(system.display.pixel(15, 15), 1);
+        assert_eq!(system.display.pixel(16, 0), 0);
+        assert_eq!(system.display.pixel(0, 16), 0);
+        assert_eq!(system.cpu().get_register(0xF), 0);
+    }
+
+    #[test]
+    fn big_font() {
+        // LD VA 0xA; LDHSPR VA
+        let mut system = system("6A0A FA30");
+        system.step_n(2).unwrap();
+        assert_eq!(system.cpu().index(), 0x150 + 10 * 10);
+        let sprite: Vec<u8> = (0..10)
+            .map(|i| system.memory().load_byte(0x1B4 + i).unwrap())
+            .collect();
+        assert_eq!(
+            sprite,
+            [0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3]
+        );
+    }
+
+    #[test]
+    fn rpl_flags() {
+        // LD V0 1; LD V1 2; LD V2 3; STRPL V2; LD V0 0; LD V1 0; LD V2 0; LDRPL V1
+        let mut system = system("6001 6102 6203 F275 6000 6100 6200 F185");
+        system.step_n(8).unwrap();
+        let registers: Vec<u8> = (0..3).map(|x| system.cpu().get_register(x)).collect();
+        assert_eq!(registers, [1, 2, 0]);
+    }
+
+    fn xo_chip(program: &str) -> System {
+        let config = Config {
+            quirks: Quirks::XO_CHIP,
+            memory_size: XO_MEMORY_SIZE,
+            ..Config::default()
+        };
+        system_with(config, program)
+    }
+
+    #[test]
+    fn long_index_load_only_in_xo_chip() {
+        // LDL I 0x1234
+        let mut xo = xo_chip("F000 1234");
+        let step = xo.step().unwrap();
+        assert!(matches!(step.instruction, Instruction::SetILong(0x1234)));
+        assert_eq!((xo.cpu().index(), xo.cpu().pc()), (0x1234, 0x204));
+
+        // HALT
+        let mut system = system("F000 1234");
+        assert!(system.step().unwrap().halted);
+    }
+
+    #[test]
+    fn skip_over_long_index_load() {
+        // SE V0 0; LDL I 0x1234; LD V1 1
+        let mut system = xo_chip("3000 F000 1234 6101");
+        system.step_n(2).unwrap();
+        assert_eq!(system.cpu().index(), 0);
+        assert_eq!(system.cpu().get_register(1), 1);
+    }
+
+    #[test]
+    fn register_range_save_and_load() {
+        // LD V1 1; LD V2 2; LD V3 3; LD I 0x300; SAVE V1 V3; LOAD V3 V1
+        let mut system = xo_chip("6101 6202 6303 A300 5132 5313");
+        system.step_n(5).unwrap();
+        let saved: Vec<u8> = (0x300..0x304)
+            .map(|addr| system.memory().load_byte(addr).unwrap())
+            .collect();
+        assert_eq!(saved, [1, 2, 3, 0]);
+        system.step().unwrap();
+        let registers: Vec<u8> = (1..4).map(|x| system.cpu().get_register(x)).collect();
+        assert_eq!(registers, [3, 2, 1]);
+        assert_eq!(system.cpu().index(), 0x300);
+    }
+
+    #[test]
+    fn planes() {
+        // PLANE 3; LD I dots; DRW V0 V0 1; PLANE 2; CLS; dots: db 0x80, 0x80
+        let mut system = xo_chip("F301 A20A D001 F201 00E0 8080");
+        system.step_n(3).unwrap();
+        assert_eq!(system.display.pixel(0, 0), 3);
+        system.step_n(2).unwrap();
+        assert_eq!(system.display.planes(), 2);
+        assert_eq!(system.display.pixel(0, 0), 1);
+    }
+
+    #[test]
+    fn audio_pattern_and_pitch() {
+        // LD I pattern; AUDIO; LD VA 0x70; PITCH VA; pattern: 16 x db 0xF0
+        let program = format!("A208 F002 6A70 FA3A {}", "F0".repeat(16));
+        let mut system = xo_chip(&program);
+        assert_eq!(system.sound.pattern(), None);
+        system.step_n(2).unwrap();
+        let pattern = system.sound.pattern().unwrap();
+        assert_eq!((pattern.bits, pattern.pitch), ([0xF0; 16], 64));
+        system.step_n(2).unwrap();
+        assert_eq!(system.sound.pattern().unwrap().pitch, 0x70);
+    }
+
+    #[test]
+    fn index_wraps_at_end_of_memory() {
+        let quirks = Quirks {
+            index_overflow_sets_vf: true,
+            ..Quirks::default()
+        };
+        // LD I 0xFFF; LD V0 2; ADDI V0
+        let program = "AFFF 6002 F01E";
+        let mut system = system_with(
+            Config {
+                quirks,
+                ..Config::default()
+            },
+            program,
+        );
+        system.step_n(3).unwrap();
+        assert_eq!(system.cpu().index(), 0x001);
+        assert_eq!(system.cpu().get_register(0xF), 1);
+
+        let mut system = system_with(
+            Config {
+                quirks,
+                memory_size: XO_MEMORY_SIZE,
+                ..Config::default()
+            },
+            program,
+        );
+        system.step_n(3).unwrap();
+        assert_eq!(system.cpu().index(), 0x1001);
+        assert_eq!(system.cpu().get_register(0xF), 0);
+    }
+
+    #[test]
+    fn key_skips_test_held_keys() {
+        let source = "LD V0 5\nLD V1 6\nSKP V0\nLD V2 1\nSKNP V1\nLD V3 1";
+        let mut system = assembled(source);
+        system.key_down(0x6);
+        system.key_down(0x5);
+        system.key_up(0x6);
+        system.step_n(6).unwrap();
+        assert_eq!(system.cpu().get_register(2), 0);
+        assert_eq!(system.cpu().get_register(3), 0);
+
+        let mut system = assembled(source);
+        system.key_down(0x6);
+        system.step_n(6).unwrap();
+        assert_eq!(system.cpu().get_register(2), 1);
+        assert_eq!(system.cpu().get_register(3), 1);
+    }
 }
@@ -1,101 +1,741 @@
+mod debug;
+mod demos;
+#[cfg(feature = "terminal")]
+mod terminal;
+
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
 use std::process;
 
-use cassowary::progloader;
-use cassowary::{Memory, MemoryError, System};
-
-fn load_firmware(mem: &mut Memory) -> Result<(), MemoryError> {
-    progloader::load_from_hex(include_str!("firmware.mem"), mem)
-}
-
-fn hex_to_decimal(mem: &mut Memory) -> Result<(), MemoryError> {
-    // This is an example from The CHIP-8 Classic Manual
-    // http://www.CHIP-8.com/
-    // hex to decimal
-    progloader::load_from_hex(
-        "0200   00E0 6380 6400 6500 A500 F333 F265 F029
-         0210   D455 F129 7408 D455 F229 7408 D455 F000
-        ",
-        mem,
-    )
-}
-
-fn double_sum(mem: &mut Memory) -> Result<(), MemoryError> {
-    // This is an example from Tim McNamara's "Rust in Action"
-    progloader::load_from_hex(
-        "0200   2100 2100 0000
-         0100   8014 8014 00EE
-        ",
-        mem,
-    )
-}
-
-fn display_a_hex_sprite(mem: &mut Memory) -> Result<(), MemoryError> {
-    // display a hex sprite
-    progloader::load_from_hex(
-        "# Display 'E'
-         # LDR $1 3; LDR $2 2; LDR $3 E; LDSPR $3; DISP $1 $2 5
-         0200   6103 6202 630E F329 D125
-        ",
-        mem,
-    )
-}
-
-fn timer_display_sprites(mem: &mut Memory) -> Result<(), MemoryError> {
-    // play sound for c. 1 s and display sprites with delay
-    progloader::load_from_hex(
-        "# LDR $3 @60; SETSOUND $3;
-         0200  633C F318
-         # LDR $7 A; CALL 300  
-         0204  670A 2300
-         # LDR $4 @30; SETDELAY $4;
-         0208  641E F415
-         # GETDELAY $4; SKIPEQ $4, 0 ; JMP 20C
-         020C  F407 3400 120C 
-         # ADDI $7 1; CALL 300
-         0212  7701 2300
-
-         # DISPCLR; LDR 5 3; LDR 6 2; LDSPR 7; DISP 5 6 5; RET;
-         0300 00E0 6503 6602 F729 D565 00EE
-        ",
-        mem,
-    )
-}
-
-fn scratch(mem: &mut Memory) -> Result<(), MemoryError> {
-    // random scratch
-    progloader::load_from_hex(
-        "# LDR $3 @10; SETSOUND $3;
-         0200  630A F318
-         0204  7A01 1204
-        ",
-        mem,
-    )
-}
-
-fn load_program(mem: &mut Memory) -> Result<(), MemoryError> {
-    match 3 {
-        1 => hex_to_decimal(mem),
-        2 => scratch(mem),
-        3 => display_a_hex_sprite(mem),
-        4 => timer_display_sprites(mem),
-        _ => double_sum(mem),
+use clap::{value_parser, Args, Parser, Subcommand, ValueEnum};
+
+#[cfg(feature = "audio-rodio")]
+use cassowary::audio::RodioAudio;
+use cassowary::audio::{AudioBackend, NullAudio, WavAudio};
+use cassowary::capture::{self, GifRecorder, Palette, DEFAULT_SCALE};
+use cassowary::movie::Movie;
+use cassowary::progloader::{self, PROGRAM_START};
+use cassowary::quirks::Quirks;
+use cassowary::render::{AsciiRenderer, Frame, NullRenderer, Renderer, Tee};
+use cassowary::rewind::DEFAULT_REWIND_BUDGET;
+use cassowary::scheduler::{self, Scheduler, FRAME_RATE};
+use cassowary::trace::{TraceFormat, Tracer};
+use cassowary::{assembler, disassembler};
+use cassowary::{
+    Config, CpuError, Instruction, InstructionClass, MemAddr, System, TimerMode,
+    INSTRUCTIONS_PER_FRAME, MEMORY_SIZE, XO_MEMORY_SIZE,
+};
+#[cfg(feature = "terminal")]
+use cassowary::{KeyMap, DEFAULT_LAYOUT};
+
+#[cfg(feature = "terminal")]
+use crate::terminal::Glyphs;
+
+/// Cassowary - A Dodgy & Shoddy CHIP-8 Emulator
+#[derive(Parser)]
+#[command(version)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Run a ROM
+    Run(RunArgs),
+    /// Run a ROM, logging every executed instruction
+    Trace(TraceArgs),
+    /// Run a ROM in an interactive debugger
+    Debug(MachineArgs),
+    /// Show information about a ROM
+    Info {
+        rom: PathBuf,
+        /// Address at which the ROM is loaded
+        #[arg(long, value_parser = parse_addr, default_value = "0x200")]
+        load_addr: MemAddr,
+    },
+    /// Disassemble a ROM
+    Disasm {
+        rom: PathBuf,
+        /// Address at which the ROM is loaded
+        #[arg(long, value_parser = parse_addr, default_value = "0x200")]
+        load_addr: MemAddr,
+        /// Address at which execution starts (default: the load address)
+        #[arg(long, value_parser = parse_addr)]
+        entry: Option<MemAddr>,
+        /// Disassemble `F000 NNNN` as the XO-CHIP `LDL I NNNN` rather than `HALT`
+        #[arg(long)]
+        xo_chip: bool,
+        /// Output file (default: stdout)
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Assemble a source file
+    Asm {
+        source: PathBuf,
+        /// Output file (default: the source file with a `.ch8` extension for
+        /// binary output, stdout for hex output)
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        #[arg(long, value_enum, default_value_t = AsmFormat::Bin)]
+        format: AsmFormat,
+    },
+    /// Run one of the built-in demo programs (lists them if no name is given)
+    Demo {
+        name: Option<String>,
+        #[command(flatten)]
+        options: SystemArgs,
+    },
+}
+
+#[derive(Args)]
+struct RunArgs {
+    #[command(flatten)]
+    machine: MachineArgs,
+    /// Dump the CPU and memory when the program halts
+    #[arg(long)]
+    dump: bool,
+    /// Record the key presses to a movie file
+    #[arg(long, value_name = "FILE")]
+    record: Option<PathBuf>,
+    /// Replay a movie file recorded with --record
+    #[arg(long, value_name = "FILE", conflicts_with = "load_state")]
+    play: Option<PathBuf>,
+    /// Save a PNG screenshot of the display when the run ends
+    #[arg(long, value_name = "FILE")]
+    png: Option<PathBuf>,
+    /// Record the display to an animated GIF
+    #[arg(long, value_name = "FILE")]
+    gif: Option<PathBuf>,
+    /// Only record the first N frames to the GIF
+    #[arg(long, value_name = "N", requires = "gif")]
+    gif_frames: Option<u64>,
+}
+
+#[derive(Args)]
+struct TraceArgs {
+    #[command(flatten)]
+    machine: MachineArgs,
+    /// Stop after this many instructions
+    #[arg(long, default_value_t = 1000)]
+    steps: usize,
+    #[arg(long, value_enum, default_value_t = TraceFormatArg::Text)]
+    format: TraceFormatArg,
+    /// Only log instructions at addresses in this range (e.g. 0x200-0x2FF)
+    #[arg(long, value_parser = parse_range)]
+    pc_range: Option<(MemAddr, MemAddr)>,
+    /// Only log instructions of these classes (comma separated): const, math,
+    /// display, cond, flow, keyboard, timer, sound, memory, random or system
+    #[arg(long, value_parser = parse_class, value_delimiter = ',')]
+    class: Vec<InstructionClass>,
+    /// Output file (default: stdout)
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+}
+
+#[derive(Args)]
+struct MachineArgs {
+    /// ROM image (raw binary, `.ch8`)
+    rom: PathBuf,
+    /// Address at which the ROM is loaded and started (e.g. 0x600 for ETI-660 programs)
+    #[arg(long, value_parser = parse_addr, default_value = "0x200")]
+    load_addr: MemAddr,
+    /// Resume from a save state (saved with the debugger's `save` command)
+    #[arg(long)]
+    load_state: Option<PathBuf>,
+    #[command(flatten)]
+    options: SystemArgs,
+}
+
+#[derive(Args)]
+struct SystemArgs {
+    /// Interpreter quirks preset: vip, chip48, schip or xochip
+    #[arg(long, value_parser = parse_quirks)]
+    quirks: Option<Quirks>,
+    /// Let `LDK VX` take a key as soon as it is pressed rather than when it is released
+    #[arg(long)]
+    key_on_press: bool,
+    /// Instructions executed per 60 Hz frame
+    #[arg(long, default_value_t = INSTRUCTIONS_PER_FRAME)]
+    speed: usize,
+    /// Instructions executed per second instead, rounded to whole
+    /// instructions per frame
+    #[arg(long, value_name = "N", conflicts_with = "speed")]
+    ips: Option<usize>,
+    /// Speed relative to real time (60 frames per second) from 0.01 to 100,
+    /// e.g. 2 to fast-forward or 0.5 for slow motion, or `max` to run as fast
+    /// as possible (default: 1, or max with `--frontend none`)
+    #[arg(long, value_name = "FACTOR", value_parser = parse_pace)]
+    pace: Option<f64>,
+    /// Seed of the random number generator
+    #[arg(long)]
+    seed: Option<u64>,
+    /// Run the timers on wall-clock time rather than emulated frames
+    #[arg(long)]
+    real_time_timers: bool,
+    /// Keep up to this many MiB of rewind history (default: 16 in the debugger, off otherwise)
+    #[arg(long, value_name = "MIB")]
+    rewind: Option<usize>,
+    /// How the display is shown
+    #[arg(long, value_enum, default_value_t = Frontend::Ascii)]
+    frontend: Frontend,
+    /// Where the buzzer is played
+    #[arg(long, value_enum, default_value_t = DEFAULT_AUDIO)]
+    audio: Audio,
+    /// Render the buzzer to this WAV file instead
+    #[arg(long, value_name = "FILE")]
+    wav: Option<PathBuf>,
+    /// Sample rate of the WAV file
+    #[arg(
+        long,
+        value_name = "HZ",
+        default_value_t = 44_100,
+        requires = "wav",
+        value_parser = value_parser!(u32).range(1..)
+    )]
+    sample_rate: u32,
+    /// Image pixels per display pixel in screenshots and recordings
+    #[arg(long, value_parser = parse_scale, default_value_t = DEFAULT_SCALE)]
+    scale: usize,
+    /// Colors of screenshots and recordings: 2 or 4 comma separated RRGGBB
+    /// colors for off, plane 1, plane 2 and both planes (default: black,
+    /// white and two greys)
+    #[arg(long, value_parser = parse_palette)]
+    palette: Option<Palette>,
+    /// How the terminal frontend draws pixels
+    #[cfg(feature = "terminal")]
+    #[arg(long, value_enum, default_value_t = Glyphs::HalfBlock)]
+    glyphs: Glyphs,
+    /// Host keys for the keypad keys 123C 456D 789E A0BF, in that order
+    #[cfg(feature = "terminal")]
+    #[arg(long, value_parser = parse_keymap, default_value = DEFAULT_LAYOUT)]
+    keymap: KeyMap,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Frontend {
+    /// Print the display as ASCII art after every frame that changed it
+    Ascii,
+    /// Draw the display in place at 60 Hz and read the keypad from the
+    /// keyboard (run and demo only)
+    #[cfg(feature = "terminal")]
+    Terminal,
+    /// Do not show the display
+    None,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Audio {
+    /// The default output device, if there is one (needs the audio-rodio feature)
+    Speaker,
+    /// No sound
+    None,
+}
+
+#[cfg(feature = "audio-rodio")]
+const DEFAULT_AUDIO: Audio = Audio::Speaker;
+#[cfg(not(feature = "audio-rodio"))]
+const DEFAULT_AUDIO: Audio = Audio::None;
+
+/// Largest image scale; keeps hi-res GIFs within their 65535 pixel limit
+const MAX_SCALE: usize = 64;
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum TraceFormatArg {
+    /// One line per instruction
+    Text,
+    /// One JSON object per line
+    Json,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum AsmFormat {
+    /// Raw binary ROM image
+    Bin,
+    /// Hex text as read by the program loader
+    Hex,
+}
+
+impl SystemArgs {
+    fn config(&self) -> Config {
+        let frame_rate = FRAME_RATE as usize;
+        let mut config = Config {
+            instructions_per_frame: match self.ips {
+                Some(ips) => ((ips + frame_rate / 2) / frame_rate).max(1),
+                None => self.speed,
+            },
+            ..Config::default()
+        };
+        if let Some(quirks) = self.quirks {
+            config.quirks = quirks;
+            // `LDL I NNNN` is the only way to address memory past 4 KiB
+            if quirks.long_index_load {
+                config.memory_size = XO_MEMORY_SIZE;
+            }
+        }
+        config.quirks.key_wait_on_press |= self.key_on_press;
+        if let Some(seed) = self.seed {
+            config.seed = seed;
+        }
+        if self.real_time_timers {
+            config.timer_mode = TimerMode::RealTime;
+        }
+        if let Some(mib) = self.rewind {
+            config.rewind_budget = mib << 20;
+        }
+        config
+    }
+
+    /// The terminal frontend takes over stdin and stdout, which `trace` and
+    /// `debug` need for themselves.
+    fn check_no_terminal(&self) -> Result<(), String> {
+        #[cfg(feature = "terminal")]
+        if self.frontend == Frontend::Terminal {
+            return Err("--frontend terminal is only supported by run and demo".to_string());
+        }
+        Ok(())
+    }
+
+    fn system(&self) -> Result<System, String> {
+        let mut system = System::with_config(self.config());
+        if let Some(path) = &self.wav {
+            let file =
+                fs::File::create(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+            let wav = WavAudio::new(io::BufWriter::new(file), self.sample_rate)
+                .map_err(|err| format!("{}: {}", path.display(), err))?;
+            system.set_audio_backend(Box::new(wav));
+        } else if self.audio == Audio::Speaker {
+            // a machine without sound can still run games
+            match speaker() {
+                Ok(speaker) => {
+                    system.set_audio_backend(speaker);
+                }
+                Err(err) => eprintln!("WARNING: no sound: {}", err),
+            }
+        }
+        system.set_renderer(self.renderer());
+        progloader::load_firmware(system.memory_mut()).map_err(|err| err.to_string())?;
+        Ok(system)
+    }
+
+    /// The scheduler running frames at the requested pace.
+    fn scheduler(&self) -> Result<Scheduler, String> {
+        let mut scheduler = Scheduler::new();
+        let pace = match self.frontend {
+            // nobody is watching
+            Frontend::None => f64::INFINITY,
+            _ => 1.0,
+        };
+        scheduler
+            .set_speed(self.pace.unwrap_or(pace))
+            .map_err(|err| err.to_string())?;
+        Ok(scheduler)
+    }
+
+    /// The renderer showing the display, if the frontend uses one.
+    fn renderer(&self) -> Box<dyn Renderer> {
+        match self.frontend {
+            Frontend::Ascii => Box::new(AsciiRenderer::new(io::stdout())),
+            _ => Box::new(NullRenderer),
+        }
+    }
+
+    /// A recorder of the display to a new GIF file.
+    fn gif_recorder(&self, path: &Path) -> Result<GifRecorder, String> {
+        let file = fs::File::create(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+        Ok(GifRecorder::new(
+            io::BufWriter::new(file),
+            self.palette.unwrap_or_default(),
+            self.scale,
+        ))
+    }
+
+    /// Saves a PNG screenshot of the display.
+    fn screenshot(&self, system: &System, path: &Path) -> Result<(), String> {
+        let frame = Frame::capture(system.display());
+        fs::File::create(path)
+            .and_then(|file| {
+                capture::write_png(
+                    io::BufWriter::new(file),
+                    &frame,
+                    &self.palette.unwrap_or_default(),
+                    self.scale,
+                )
+            })
+            .map_err(|err| format!("{}: {}", path.display(), err))
     }
 }
 
-fn main() {
-    println!("Cassowary - A Dodgy & Shoddy CHIP-8 Emulator");
-    let mut system = System::new().expect("setup failed");
-    {
+impl MachineArgs {
+    fn system(&self) -> Result<System, String> {
+        let mut system = self.options.system()?;
         let mem = system.memory_mut();
-        load_firmware(mem).unwrap();
-        load_program(mem).unwrap()
+        progloader::load_binary_file(&self.rom, mem, self.load_addr)
+            .map_err(|err| format!("{}: {}", self.rom.display(), err))?;
+        if self.load_addr != PROGRAM_START {
+            progloader::set_entry_point(mem, self.load_addr).map_err(|err| err.to_string())?;
+        }
+        if let Some(path) = &self.load_state {
+            let state = fs::read(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+            system
+                .load_state(&state)
+                .map_err(|err| format!("{}: {}", path.display(), err))?;
+        }
+        Ok(system)
     }
+}
+
+fn parse_addr(s: &str) -> Result<MemAddr, String> {
+    let parsed = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => MemAddr::from_str_radix(hex, 16),
+        None => s.parse(),
+    };
+    parsed.map_err(|err| format!("invalid address {:?}: {}", s, err))
+}
+
+fn parse_range(s: &str) -> Result<(MemAddr, MemAddr), String> {
+    let (start, end) = s
+        .split_once('-')
+        .ok_or_else(|| format!("invalid range {:?} (expected START-END)", s))?;
+    Ok((parse_addr(start)?, parse_addr(end)?))
+}
+
+fn parse_class(s: &str) -> Result<InstructionClass, String> {
+    InstructionClass::from_name(s).ok_or_else(|| {
+        let names: Vec<&str> = InstructionClass::ALL.iter().map(|c| c.name()).collect();
+        format!(
+            "unknown instruction class {:?} (expected one of: {})",
+            s,
+            names.join(", ")
+        )
+    })
+}
+
+fn parse_scale(s: &str) -> Result<usize, String> {
+    match s.parse() {
+        Ok(scale @ 1..=MAX_SCALE) => Ok(scale),
+        _ => Err(format!(
+            "invalid scale {:?} (expected 1 to {})",
+            s, MAX_SCALE
+        )),
+    }
+}
+
+fn parse_pace(s: &str) -> Result<f64, String> {
+    if s == "max" {
+        return Ok(f64::INFINITY);
+    }
+    s.parse()
+        .ok()
+        .filter(|pace: &f64| (scheduler::MIN_SPEED..=scheduler::MAX_SPEED).contains(pace))
+        .ok_or_else(|| {
+            format!(
+                "invalid pace {:?} (expected a number from {} to {} or max)",
+                s,
+                scheduler::MIN_SPEED,
+                scheduler::MAX_SPEED
+            )
+        })
+}
 
-    if let Err(err) = system.run() {
+fn parse_palette(s: &str) -> Result<Palette, String> {
+    s.parse()
+        .map_err(|err: capture::PaletteError| err.to_string())
+}
+
+#[cfg(feature = "terminal")]
+fn parse_keymap(s: &str) -> Result<KeyMap, String> {
+    KeyMap::from_layout(s).map_err(|err| err.to_string())
+}
+
+fn parse_quirks(s: &str) -> Result<Quirks, String> {
+    Quirks::preset(s).ok_or_else(|| {
+        format!(
+            "unknown quirks preset {:?} (expected one of: {})",
+            s,
+            Quirks::PRESETS.join(", ")
+        )
+    })
+}
+
+fn main() {
+    let cli = Cli::parse();
+    let result = match cli.command {
+        Command::Run(args) => run(args),
+        Command::Trace(args) => trace(args),
+        Command::Debug(machine) => debug(machine),
+        Command::Info { rom, load_addr } => info(rom, load_addr),
+        Command::Disasm {
+            rom,
+            load_addr,
+            entry,
+            xo_chip,
+            output,
+        } => disasm(rom, load_addr, entry, xo_chip, output),
+        Command::Asm {
+            source,
+            output,
+            format,
+        } => asm(source, output, format),
+        Command::Demo { name, options } => demo(name, options),
+    };
+    if let Err(err) = result {
         eprintln!("ERROR: {}", err);
         process::exit(1);
     }
+}
+
+fn run(args: RunArgs) -> Result<(), String> {
+    let mut system = args.machine.system()?;
+    if let Some(path) = &args.play {
+        let movie = read_movie(path)?;
+        system
+            .play_movie(movie)
+            .map_err(|err| format!("{}: {}", path.display(), err))?;
+    }
+    if args.record.is_some() {
+        system.start_recording();
+    }
+    let options = &args.machine.options;
+    if let Some(path) = &args.gif {
+        let mut gif = options.gif_recorder(path)?;
+        if let Some(frames) = args.gif_frames {
+            gif = gif.with_limit(frames);
+        }
+        let shown = system.set_renderer(Box::new(NullRenderer));
+        system.set_renderer(Box::new(Tee::new(shown, Box::new(gif))));
+    }
+    let result = run_system(&mut system, options);
+    // keep the movie and pictures of a failed run too, e.g. for a bug report
+    if let (Some(path), Some(movie)) = (&args.record, system.stop_recording()) {
+        fs::write(path, movie.to_bytes()).map_err(|err| format!("{}: {}", path.display(), err))?;
+    }
+    if let Some(path) = &args.gif {
+        system
+            .set_renderer(Box::new(NullRenderer))
+            .finish()
+            .map_err(|err| format!("{}: {}", path.display(), err))?;
+    }
+    if let Some(path) = &args.png {
+        options.screenshot(&system, path)?;
+    }
+    result?;
+    if args.dump {
+        system.cpu().dump();
+        system.memory().dump();
+    }
+    Ok(())
+}
+
+fn read_movie(path: &PathBuf) -> Result<Movie, String> {
+    let bytes = fs::read(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+    Movie::from_bytes(&bytes).map_err(|err| format!("{}: {}", path.display(), err))
+}
+
+fn run_system(system: &mut System, options: &SystemArgs) -> Result<(), String> {
+    let result = run_frontend(system, options);
+    finish_audio(system, options)?;
+    result
+}
+
+fn run_frontend(system: &mut System, options: &SystemArgs) -> Result<(), String> {
+    let mut scheduler = options.scheduler()?;
+    #[cfg(feature = "terminal")]
+    if options.frontend == Frontend::Terminal {
+        return terminal::run(system, &mut scheduler, options.glyphs, options.keymap);
+    }
+    let summary = scheduler
+        .run(system)
+        .map_err(|err: CpuError| format!("{} (PC: {:03X})", err, system.cpu().pc()))?;
+    if !summary.halted {
+        eprintln!("WARNING: stopped waiting for a key, which this frontend cannot press");
+    }
+    Ok(())
+}
+
+#[cfg(feature = "audio-rodio")]
+fn speaker() -> Result<Box<dyn AudioBackend>, String> {
+    Ok(Box::new(RodioAudio::new().map_err(|err| err.to_string())?))
+}
 
+#[cfg(not(feature = "audio-rodio"))]
+fn speaker() -> Result<Box<dyn AudioBackend>, String> {
+    Err("built without the audio-rodio feature".to_string())
+}
+
+/// Writes out the WAV file, if any.
+fn finish_audio(system: &mut System, options: &SystemArgs) -> Result<(), String> {
+    let result = system.set_audio_backend(Box::new(NullAudio)).finish();
+    match &options.wav {
+        Some(path) => result.map_err(|err| format!("{}: {}", path.display(), err)),
+        None => result.map_err(|err| err.to_string()),
+    }
+}
+
+fn trace(args: TraceArgs) -> Result<(), String> {
+    args.machine.options.check_no_terminal()?;
+    let mut system = args.machine.system()?;
+    // Display frames would be interleaved with the log
+    if args.output.is_none() {
+        system.set_renderer(Box::new(NullRenderer));
+    }
+    let format = match args.format {
+        TraceFormatArg::Text => TraceFormat::Text,
+        TraceFormatArg::Json => TraceFormat::JsonLines,
+    };
+    let mut tracer = match &args.output {
+        Some(path) => {
+            let file =
+                fs::File::create(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+            Tracer::new(io::BufWriter::new(file), format)
+        }
+        None => Tracer::new(io::stdout(), format),
+    };
+    if let Some((start, end)) = args.pc_range {
+        tracer = tracer.with_pc_range(start..=end);
+    }
+    if !args.class.is_empty() {
+        tracer = tracer.with_classes(&args.class);
+    }
+    system.set_tracer(tracer);
+    let summary = system
+        .step_n(args.steps)
+        .map_err(|err| format!("{} (PC: {:03X})", err, system.cpu().pc()));
+    if let Some(tracer) = system.take_tracer() {
+        tracer.finish().map_err(|err| err.to_string())?;
+    }
+    finish_audio(&mut system, &args.machine.options)?;
+    summary?;
+    Ok(())
+}
+
+fn debug(machine: MachineArgs) -> Result<(), String> {
+    machine.options.check_no_terminal()?;
+    let mut system = machine.system()?;
+    if machine.options.rewind.is_none() {
+        system.enable_rewind(DEFAULT_REWIND_BUDGET);
+    }
+    let result = debug::repl(&mut system, &machine.options);
+    finish_audio(&mut system, &machine.options)?;
+    result
+}
+
+fn info(rom: PathBuf, load_addr: MemAddr) -> Result<(), String> {
+    let bytes = fs::read(&rom).map_err(|err| format!("{}: {}", rom.display(), err))?;
+    println!("ROM:       {}", rom.display());
+    println!("Size:      {} bytes", bytes.len());
+    println!(
+        "Loads at:  {:03X}-{:03X}",
+        load_addr,
+        load_addr + bytes.len().saturating_sub(1)
+    );
+    let platform = if load_addr + bytes.len() > MEMORY_SIZE {
+        "XO-CHIP (needs more than 4 KiB)"
+    } else {
+        let opcodes = bytes
+            .chunks_exact(2)
+            .map(|word| u16::from_be_bytes([word[0], word[1]]));
+        let mut platform = "CHIP-8";
+        for instr in opcodes.map(Instruction::decode) {
+            match platform_of(instr) {
+                Some("XO-CHIP") => {
+                    platform = "XO-CHIP";
+                    break;
+                }
+                Some(other) => platform = other,
+                None => {}
+            }
+        }
+        platform
+    };
+    println!("Platform:  {} (guessed from instructions)", platform);
+    Ok(())
+}
+
+/// Extension an instruction belongs to, if any.
+fn platform_of(instr: Instruction) -> Option<&'static str> {
+    match instr {
+        Instruction::ScrollDown(_)
+        | Instruction::ScrollRight
+        | Instruction::ScrollLeft
+        | Instruction::LoRes
+        | Instruction::HiRes
+        | Instruction::Exit
+        | Instruction::BigSpriteAddrIX(_)
+        | Instruction::StoreFlagsX(_)
+        | Instruction::LoadFlagsX(_)
+        | Instruction::DispDraw(_, _, 0) => Some("SUPER-CHIP"),
+        Instruction::ScrollUp(_)
+        | Instruction::SelectPlanes(_)
+        | Instruction::SetILong(_)
+        | Instruction::RegDumpRangeIXY(_, _)
+        | Instruction::RegLoadRangeIXY(_, _)
+        | Instruction::LoadAudio
+        | Instruction::SetPitchX(_) => Some("XO-CHIP"),
+        _ => None,
+    }
+}
+
+fn disasm(
+    rom: PathBuf,
+    load_addr: MemAddr,
+    entry: Option<MemAddr>,
+    xo_chip: bool,
+    output: Option<PathBuf>,
+) -> Result<(), String> {
+    let bytes = fs::read(&rom).map_err(|err| format!("{}: {}", rom.display(), err))?;
+    let entry = entry.unwrap_or(load_addr);
+    let source = disassembler::disassemble(&bytes, load_addr, entry, xo_chip);
+    match output {
+        Some(path) => {
+            fs::write(&path, source).map_err(|err| format!("{}: {}", path.display(), err))
+        }
+        None => io::stdout()
+            .write_all(source.as_bytes())
+            .map_err(|err| err.to_string()),
+    }
+}
+
+fn asm(source: PathBuf, output: Option<PathBuf>, format: AsmFormat) -> Result<(), String> {
+    let text =
+        fs::read_to_string(&source).map_err(|err| format!("{}: {}", source.display(), err))?;
+    let program =
+        assembler::assemble(&text).map_err(|err| format!("{}: {}", source.display(), err))?;
+    let output = match format {
+        AsmFormat::Bin => Some(output.unwrap_or_else(|| source.with_extension("ch8"))),
+        AsmFormat::Hex => output,
+    };
+    let bytes = match format {
+        AsmFormat::Bin => program.to_binary(),
+        AsmFormat::Hex => program.to_hex().into_bytes(),
+    };
+    match output {
+        Some(path) => fs::write(&path, bytes).map_err(|err| format!("{}: {}", path.display(), err)),
+        None => io::stdout()
+            .write_all(&bytes)
+            .map_err(|err| err.to_string()),
+    }
+}
+
+fn demo(name: Option<String>, options: SystemArgs) -> Result<(), String> {
+    let demo = match name.as_deref().map(demos::find) {
+        Some(Some(demo)) => demo,
+        Some(None) => return Err(format!("unknown demo {:?}", name.unwrap())),
+        None => {
+            println!("Demos:");
+            for demo in demos::DEMOS.iter() {
+                println!("  {:16} {}", demo.name, demo.description);
+            }
+            return Ok(());
+        }
+    };
+    println!("Cassowary - A Dodgy & Shoddy CHIP-8 Emulator");
+    let mut system = options.system()?;
+    demo.load(system.memory_mut())
+        .map_err(|err| err.to_string())?;
+    run_system(&mut system, &options)?;
     system.cpu().dump();
     system.memory().dump();
+    Ok(())
 }
@@ -1,3 +1,5 @@
+use std::cell::RefCell;
+
 use thiserror::Error;
 
 use crate::instructions::MemAddr;
@@ -6,20 +8,113 @@ use crate::instructions::MemAddr;
 pub enum MemoryError {
     #[error("Out of Bounds")]
     OutOfBounds,
+    #[error("ROM too large: {rom_size} bytes, but only {available} bytes available")]
+    RomTooLarge { rom_size: usize, available: usize },
+}
+
+/// Size of the classic CHIP-8 memory (4 KiB)
+pub const MEMORY_SIZE: usize = 0x1000;
+/// Size of the XO-CHIP memory (64 KiB)
+pub const XO_MEMORY_SIZE: usize = 0x10000;
+
+/// A kind of memory access by the CPU.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+    /// Instruction fetch
+    Execute,
+}
+
+/// What happens when a watchpoint is hit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchAction {
+    /// Stop execution in the [`debugger`](crate::debugger)
+    Break,
+    /// Only record the access
+    Log,
+}
+
+/// Watches CPU accesses to the addresses `start..=end`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub start: MemAddr,
+    pub end: MemAddr,
+    pub read: bool,
+    pub write: bool,
+    pub execute: bool,
+    pub action: WatchAction,
+}
+
+impl Watchpoint {
+    fn matches(&self, addr: MemAddr, access: Access) -> bool {
+        let watched = match access {
+            Access::Read => self.read,
+            Access::Write => self.write,
+            Access::Execute => self.execute,
+        };
+        watched && (self.start..=self.end).contains(&addr)
+    }
+}
+
+/// An access that hit a watchpoint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WatchEvent {
+    pub access: Access,
+    pub addr: MemAddr,
+    /// Address of the instruction that accessed the memory
+    pub pc: MemAddr,
+    /// Value before the access
+    pub old: u8,
+    /// Value after the access (the same as `old` unless written)
+    pub new: u8,
+    pub action: WatchAction,
+}
+
+pub struct Memory {
+    bytes: Vec<u8>,
+    watchpoints: Vec<Watchpoint>,
+    /// accesses that hit a watchpoint, recorded by `&self` loads too
+    events: RefCell<Vec<WatchEvent>>,
+    /// address of the instruction being executed
+    pc: MemAddr,
+    /// writes by the CPU, if recorded (see [`crate::trace`])
+    writes: Option<Vec<(MemAddr, u8)>>,
 }
 
-pub struct Memory([u8; 4096]);
+impl Default for Memory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 impl Memory {
     pub fn new() -> Self {
-        Self([0; 4096])
+        Self::with_size(MEMORY_SIZE)
+    }
+
+    pub fn with_size(size: usize) -> Self {
+        Self {
+            bytes: vec![0; size],
+            watchpoints: Vec::new(),
+            events: RefCell::new(Vec::new()),
+            pc: 0,
+            writes: None,
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.bytes.len()
     }
 
     pub fn dump(&self) {
         const BLOCK: usize = 16;
         let mut skipped = false;
         println!("Memory:");
-        for (addr, block) in (0..self.0.len()).step_by(BLOCK).zip(self.0.chunks(BLOCK)) {
+        for (addr, block) in (0..self.bytes.len())
+            .step_by(BLOCK)
+            .zip(self.bytes.chunks(BLOCK))
+        {
             if block.iter().copied().all(|x| x == 0) {
                 skipped = true;
             } else {
@@ -27,7 +122,7 @@ impl Memory {
                     println!("   ...");
                 }
                 print!(" {:03X}:", addr);
-                for (i, b) in block.into_iter().enumerate() {
+                for (i, b) in block.iter().enumerate() {
                     if i % 2 == 0 {
                         print!(" ");
                     }
@@ -43,37 +138,162 @@ impl Memory {
     }
 
     pub fn set_mem_from(&mut self, start: MemAddr, data: &[u8]) -> Result<(), MemoryError> {
-        if start >= self.0.len() || (start + data.len()) > self.0.len() {
+        let end = start
+            .checked_add(data.len())
+            .ok_or(MemoryError::OutOfBounds)?;
+        if end > self.bytes.len() {
             return Err(MemoryError::OutOfBounds);
         }
-        self.0[start..(start + data.len())].copy_from_slice(data);
+        self.bytes[start..end].copy_from_slice(data);
         Ok(())
     }
 
+    /// Returns `len` bytes starting at `start`.
+    pub fn get_mem(&self, start: MemAddr, len: usize) -> Result<&[u8], MemoryError> {
+        start
+            .checked_add(len)
+            .and_then(|end| self.bytes.get(start..end))
+            .ok_or(MemoryError::OutOfBounds)
+    }
+
+    pub(crate) fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    pub(crate) fn bytes_mut(&mut self) -> &mut [u8] {
+        &mut self.bytes
+    }
+
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        self.watchpoints.push(watchpoint);
+    }
+
+    /// Removes the `n`th watchpoint (in the order they were added).
+    pub fn remove_watchpoint(&mut self, n: usize) -> Option<Watchpoint> {
+        (n < self.watchpoints.len()).then(|| self.watchpoints.remove(n))
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    /// Returns and forgets the accesses that hit a watchpoint so far.
+    pub fn take_watch_events(&mut self) -> Vec<WatchEvent> {
+        std::mem::take(self.events.get_mut())
+    }
+
+    /// Sets the address of the instruction recorded with watched accesses.
+    pub(crate) fn set_pc(&mut self, pc: MemAddr) {
+        self.pc = pc;
+    }
+
+    /// Starts or stops recording the CPU's writes.
+    pub(crate) fn record_writes(&mut self, record: bool) {
+        self.writes = record.then(Vec::new);
+    }
+
+    /// Returns and forgets the recorded writes, as address and value.
+    pub(crate) fn take_writes(&mut self) -> Vec<(MemAddr, u8)> {
+        self.writes.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+
+    fn watch(&self, addr: MemAddr, access: Access, new: u8) {
+        if self.watchpoints.is_empty() {
+            return;
+        }
+        let old = self.bytes[addr];
+        let hits = self
+            .watchpoints
+            .iter()
+            .filter(|wp| wp.matches(addr, access));
+        // a single event per access, breaking if any of the watchpoints breaks
+        let action = hits.fold(None, |action, wp| match action {
+            Some(WatchAction::Break) => action,
+            _ => Some(wp.action),
+        });
+        if let Some(action) = action {
+            self.events.borrow_mut().push(WatchEvent {
+                access,
+                addr,
+                pc: self.pc,
+                old,
+                new,
+                action,
+            });
+        }
+    }
+
+    /// Fetches an instruction word, watched as [`Access::Execute`].
+    pub(crate) fn fetch_u16(&self, addr: MemAddr) -> Result<u16, MemoryError> {
+        let word = self.load_u16(addr)?;
+        self.watch(addr, Access::Execute, self.bytes[addr]);
+        self.watch(addr + 1, Access::Execute, self.bytes[addr + 1]);
+        Ok(word)
+    }
+
+    /// Loads a word without watching it, e.g. to look ahead at an instruction.
     pub(crate) fn load_u16(&self, addr: MemAddr) -> Result<u16, MemoryError> {
-        if addr >= self.0.len() {
+        if addr + 1 >= self.bytes.len() {
             return Err(MemoryError::OutOfBounds);
         }
 
-        let high_byte = self.0[addr as usize] as u16;
-        let low_byte = self.0[addr as usize + 1] as u16;
+        let high_byte = self.bytes[addr] as u16;
+        let low_byte = self.bytes[addr + 1] as u16;
         Ok((high_byte << 8) | low_byte)
     }
 
     pub(crate) fn store_byte(&mut self, addr: MemAddr, value: u8) -> Result<(), MemoryError> {
-        if addr >= self.0.len() {
+        if addr >= self.bytes.len() {
             return Err(MemoryError::OutOfBounds);
         }
 
-        self.0[addr] = value;
+        self.watch(addr, Access::Write, value);
+        if let Some(writes) = &mut self.writes {
+            writes.push((addr, value));
+        }
+        self.bytes[addr] = value;
         Ok(())
     }
 
     pub(crate) fn load_byte(&self, addr: MemAddr) -> Result<u8, MemoryError> {
-        if addr >= self.0.len() {
+        if addr >= self.bytes.len() {
             return Err(MemoryError::OutOfBounds);
         }
 
-        Ok(self.0[addr])
+        self.watch(addr, Access::Read, self.bytes[addr]);
+        Ok(self.bytes[addr])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ranges_past_the_end_are_out_of_bounds() {
+        let mut mem = Memory::new();
+        mem.set_mem_from(MEMORY_SIZE - 2, &[1, 2]).unwrap();
+        assert_eq!(mem.get_mem(MEMORY_SIZE - 2, 2).unwrap(), [1, 2]);
+        assert!(matches!(
+            mem.set_mem_from(MEMORY_SIZE - 1, &[1, 2]),
+            Err(MemoryError::OutOfBounds)
+        ));
+        assert!(matches!(
+            mem.get_mem(MEMORY_SIZE - 1, 2),
+            Err(MemoryError::OutOfBounds)
+        ));
+    }
+
+    #[test]
+    fn overflowing_ranges_are_out_of_bounds() {
+        let mut mem = Memory::new();
+        assert!(matches!(
+            mem.set_mem_from(usize::MAX, &[1]),
+            Err(MemoryError::OutOfBounds)
+        ));
+        assert!(matches!(
+            mem.get_mem(1, usize::MAX),
+            Err(MemoryError::OutOfBounds)
+        ));
     }
 }
@@ -0,0 +1,308 @@
+//! Input recording and replay, see [`System::start_recording`](crate::System::start_recording).
+//!
+//! A movie holds the [save state](crate::state) the recording started from,
+//! the configuration needed to run it the same way and every key change with
+//! the frame (and instruction within the frame) at which it happened.
+//! Replaying a movie restores the state and applies the key changes at the
+//! same points, which reproduces the recorded run bit-exactly as long as the
+//! timers are emulated and the random source can be saved.
+//!
+//! File format (integers are little-endian):
+//!
+//! | Size      | Field                                               |
+//! |-----------|-----------------------------------------------------|
+//! | 4         | magic: `C8MV`                                       |
+//! | 2         | format version: [`VERSION`]                         |
+//! | 4         | instructions per frame                              |
+//! | 10        | quirks (one byte per quirk, see [`Quirks`])         |
+//! | 4 + N     | length and contents of the starting save state      |
+//! | 4         | number of key changes                               |
+//! | 14 each   | frame (8), instruction within the frame (4), key, 1 = down / 0 = up |
+//! | 4         | CRC-32 (IEEE) of everything before it               |
+
+use thiserror::Error;
+
+use crate::quirks::{IndexIncrement, Quirks};
+use crate::state::{crc32, Reader, StateError, Writer};
+use crate::timer::TimerMode;
+use crate::Config;
+
+const MAGIC: &[u8; 4] = b"C8MV";
+/// Current version of the movie format
+pub const VERSION: u16 = 1;
+
+#[derive(Error, Debug)]
+pub enum MovieError {
+    #[error("not a movie")]
+    NotAMovie,
+    #[error("unsupported movie version {0} (supported up to {VERSION})")]
+    UnsupportedVersion(u16),
+    #[error("corrupt movie")]
+    Corrupt,
+    #[error("movie was recorded with a different {0}")]
+    ConfigMismatch(&'static str),
+    #[error("movie has an invalid starting state: {0}")]
+    State(#[from] StateError),
+}
+
+/// A change of a key's state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyEvent {
+    pub frame: u64,
+    /// Instructions executed in the frame before the change
+    pub offset: usize,
+    pub key: u8,
+    pub down: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Movie {
+    pub instructions_per_frame: usize,
+    pub quirks: Quirks,
+    /// Save state the recording starts from
+    pub start: Vec<u8>,
+    /// Key changes in the order they happened
+    pub events: Vec<KeyEvent>,
+}
+
+impl Movie {
+    pub(crate) fn new(config: &Config, start: Vec<u8>) -> Self {
+        Self {
+            instructions_per_frame: config.instructions_per_frame,
+            quirks: config.quirks,
+            start,
+            events: Vec::new(),
+        }
+    }
+
+    /// Checks that the movie can be replayed exactly with `config`, which must
+    /// use emulated timers.
+    pub fn check_config(&self, config: &Config) -> Result<(), MovieError> {
+        if self.instructions_per_frame != config.instructions_per_frame {
+            Err(MovieError::ConfigMismatch(
+                "number of instructions per frame",
+            ))
+        } else if config.timer_mode != TimerMode::Emulated {
+            Err(MovieError::ConfigMismatch("timer mode"))
+        } else if self.quirks != config.quirks {
+            Err(MovieError::ConfigMismatch("set of quirks"))
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Writer(Vec::new());
+        out.bytes(MAGIC);
+        out.bytes(&VERSION.to_le_bytes());
+        out.u32(self.instructions_per_frame as u32);
+        out.bytes(&quirks_to_bytes(&self.quirks));
+        out.u32(self.start.len() as u32);
+        out.bytes(&self.start);
+        out.u32(self.events.len() as u32);
+        for event in &self.events {
+            out.u64(event.frame);
+            out.u32(event.offset as u32);
+            out.u8(event.key);
+            out.bool(event.down);
+        }
+        let checksum = crc32(&out.0);
+        out.u32(checksum);
+        out.0
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, MovieError> {
+        if bytes.len() < 6 || &bytes[..4] != MAGIC {
+            return Err(MovieError::NotAMovie);
+        }
+        let version = u16::from_le_bytes([bytes[4], bytes[5]]);
+        if version == 0 || version > VERSION {
+            return Err(MovieError::UnsupportedVersion(version));
+        }
+        let Some((body, checksum)) = bytes.split_at_checked(bytes.len().saturating_sub(4)) else {
+            return Err(MovieError::Corrupt);
+        };
+        if body.len() < 6 || crc32(body).to_le_bytes() != checksum {
+            return Err(MovieError::Corrupt);
+        }
+
+        let corrupt = |_| MovieError::Corrupt;
+        let mut input = Reader(&body[6..]);
+        let instructions_per_frame = input.u32().map_err(corrupt)? as usize;
+        let quirks = quirks_from_bytes(input.array().map_err(corrupt)?)?;
+        let len = input.u32().map_err(corrupt)? as usize;
+        let start = input.take(len).map_err(corrupt)?.to_vec();
+        let count = input.u32().map_err(corrupt)?;
+        let mut events = Vec::new();
+        for _ in 0..count {
+            events.push(KeyEvent {
+                frame: input.u64().map_err(corrupt)?,
+                offset: input.u32().map_err(corrupt)? as usize,
+                key: input.u8().map_err(corrupt)?,
+                down: input.bool().map_err(corrupt)?,
+            });
+        }
+        if !input.0.is_empty() {
+            return Err(MovieError::Corrupt);
+        }
+        Ok(Self {
+            instructions_per_frame,
+            quirks,
+            start,
+            events,
+        })
+    }
+}
+
+fn quirks_to_bytes(quirks: &Quirks) -> [u8; 10] {
+    let load_store_index = match quirks.load_store_index {
+        IndexIncrement::Unchanged => 0,
+        IndexIncrement::ByX => 1,
+        IndexIncrement::ByXPlusOne => 2,
+    };
+    [
+        quirks.shift_uses_vy as u8,
+        load_store_index,
+        quirks.jump_uses_vx as u8,
+        quirks.clip_sprites as u8,
+        quirks.logic_resets_vf as u8,
+        quirks.display_wait as u8,
+        quirks.index_overflow_sets_vf as u8,
+        quirks.count_collisions as u8,
+        quirks.long_index_load as u8,
+        quirks.key_wait_on_press as u8,
+    ]
+}
+
+fn quirks_from_bytes(bytes: [u8; 10]) -> Result<Quirks, MovieError> {
+    let flag = |byte: u8| match byte {
+        0 => Ok(false),
+        1 => Ok(true),
+        _ => Err(MovieError::Corrupt),
+    };
+    let load_store_index = match bytes[1] {
+        0 => IndexIncrement::Unchanged,
+        1 => IndexIncrement::ByX,
+        2 => IndexIncrement::ByXPlusOne,
+        _ => return Err(MovieError::Corrupt),
+    };
+    Ok(Quirks {
+        shift_uses_vy: flag(bytes[0])?,
+        load_store_index,
+        jump_uses_vx: flag(bytes[2])?,
+        clip_sprites: flag(bytes[3])?,
+        logic_resets_vf: flag(bytes[4])?,
+        display_wait: flag(bytes[5])?,
+        index_overflow_sets_vf: flag(bytes[6])?,
+        count_collisions: flag(bytes[7])?,
+        long_index_load: flag(bytes[8])?,
+        key_wait_on_press: flag(bytes[9])?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{assembled, assembled_with};
+    use crate::System;
+
+    const PROGRAM: &str = "
+        loop:   LDK V0
+                LDSPR V0
+                DRW V1 V2 5
+                ADD V1 5
+                RND V3 0xFF
+                SKP V4
+                ADD V2 1
+                JP loop";
+
+    /// Taps a key a few instructions into each of a few frames.
+    fn play_keys(system: &mut System, keys: &[(u64, u8)]) {
+        for &(frame, key) in keys {
+            while system.frame() < frame {
+                system.run_frame().unwrap();
+            }
+            system.step_n(3).unwrap();
+            system.key_down(key);
+            system.step_n(4).unwrap();
+            system.key_up(key);
+        }
+        system.run_frame().unwrap();
+    }
+
+    fn replayed(movie: Movie, cycles: u64) -> System {
+        let mut replay = assembled("");
+        replay.play_movie(movie).unwrap();
+        while replay.cycles() < cycles {
+            replay.step().unwrap();
+        }
+        replay
+    }
+
+    #[test]
+    fn replay_reproduces_the_recording() {
+        let mut system = assembled(PROGRAM);
+        system.start_recording();
+        play_keys(&mut system, &[(2, 0x3), (5, 0x7), (9, 0x0), (10, 0xA)]);
+        let movie = system.stop_recording().unwrap();
+        assert_eq!(movie.events.len(), 8);
+
+        let movie = Movie::from_bytes(&movie.to_bytes()).unwrap();
+        let replay = replayed(movie, system.cycles());
+        assert!(!replay.is_playing_movie());
+        assert_eq!(replay.save_state(), system.save_state());
+    }
+
+    #[test]
+    fn replayed_keys_are_recorded_again() {
+        let mut system = assembled(PROGRAM);
+        system.start_recording();
+        play_keys(&mut system, &[(2, 0x3), (5, 0x7)]);
+        let movie = system.stop_recording().unwrap();
+
+        let mut extended = assembled("");
+        extended.play_movie(movie.clone()).unwrap();
+        extended.start_recording();
+        play_keys(&mut extended, &[(9, 0x0)]);
+        let extension = extended.stop_recording().unwrap();
+        assert_eq!(extension.events[..4], movie.events[..]);
+        assert_eq!(extension.events.len(), 6);
+
+        let replay = replayed(extension, extended.cycles());
+        assert_eq!(replay.save_state(), extended.save_state());
+    }
+
+    #[test]
+    fn mismatched_config_is_rejected() {
+        let config = Config::default();
+        let movie = Movie::new(&config, assembled("").save_state());
+        assert!(movie.check_config(&config).is_ok());
+
+        let faster = Config {
+            instructions_per_frame: config.instructions_per_frame + 1,
+            ..config
+        };
+        let real_time = Config {
+            timer_mode: TimerMode::RealTime,
+            ..config
+        };
+        let schip = Config {
+            quirks: Quirks::SUPER_CHIP,
+            ..config
+        };
+        for (config, field) in [
+            (faster, "number of instructions per frame"),
+            (real_time, "timer mode"),
+            (schip, "set of quirks"),
+        ] {
+            assert!(matches!(
+                movie.check_config(&config),
+                Err(MovieError::ConfigMismatch(mismatch)) if mismatch == field
+            ));
+            assert!(matches!(
+                assembled_with(config, "").play_movie(movie.clone()),
+                Err(MovieError::ConfigMismatch(_))
+            ));
+        }
+    }
+}
@@ -1,5 +1,64 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use thiserror::Error;
+
+use crate::instructions::MemAddr;
 use crate::memory::{Memory, MemoryError};
 
+/// Where programs are loaded and start running
+pub const PROGRAM_START: MemAddr = 0x200;
+/// Where programs for the ETI-660 are loaded and start running
+pub const ETI_660_PROGRAM_START: MemAddr = 0x600;
+
+#[derive(Error, Debug)]
+pub enum LoadError {
+    #[error("error reading ROM: {0}")]
+    Io(#[from] io::Error),
+    #[error("error loading ROM: {0}")]
+    Memory(#[from] MemoryError),
+}
+
+/// Loads the built-in firmware: the hex character sprites and a jump to
+/// [`PROGRAM_START`] at `0x000`, where the CPU starts.
+pub fn load_firmware(mem: &mut Memory) -> Result<(), MemoryError> {
+    load_from_hex(include_str!("firmware.mem"), mem)
+}
+
+/// Makes the firmware jump to `entry` rather than [`PROGRAM_START`].
+pub fn set_entry_point(mem: &mut Memory, entry: MemAddr) -> Result<(), MemoryError> {
+    if entry > 0x0FFF {
+        return Err(MemoryError::OutOfBounds);
+    }
+    let jump = 0x1000 | entry as u16;
+    mem.set_mem_from(0x000, &jump.to_be_bytes())
+}
+
+/// Loads a raw (`.ch8`) ROM image at `load_addr`,
+/// usually [`PROGRAM_START`] (or [`ETI_660_PROGRAM_START`]).
+pub fn load_binary(rom: &[u8], mem: &mut Memory, load_addr: MemAddr) -> Result<(), MemoryError> {
+    let available = mem.size().saturating_sub(load_addr);
+    if rom.len() > available {
+        return Err(MemoryError::RomTooLarge {
+            rom_size: rom.len(),
+            available,
+        });
+    }
+    mem.set_mem_from(load_addr, rom)
+}
+
+/// Reads a raw (`.ch8`) ROM image from a file and loads it at `load_addr`.
+pub fn load_binary_file<P: AsRef<Path>>(
+    path: P,
+    mem: &mut Memory,
+    load_addr: MemAddr,
+) -> Result<(), LoadError> {
+    let rom = fs::read(path)?;
+    load_binary(&rom, mem, load_addr)?;
+    Ok(())
+}
+
 pub fn load_from_hex(hex_def: &str, mem: &mut Memory) -> Result<(), MemoryError> {
     for (addr, data) in hex_to_bin(hex_def) {
         mem.set_mem_from(addr as usize, &data)?;
@@ -23,12 +82,12 @@ fn hex_to_bin(hex_def: &str) -> Vec<(u16, Vec<u8>)> {
             }
 
             if let Some(addr) = hex_to_u16(parts[0]) {
-                let data: String = (&parts[1..]).into_iter().map(|&x| x).collect();
-                if data.len() % 2 != 0 {
+                let data: String = parts[1..].concat();
+                if !data.len().is_multiple_of(2) {
                     eprintln!("Bogus line: {}", line);
                     return None;
                 }
-                let nibbles: Vec<u8> = data.bytes().map(|c| hex_to_u8(c)).collect();
+                let nibbles: Vec<u8> = data.bytes().map(hex_to_u8).collect();
 
                 let mut bytes: Vec<u8> = Vec::with_capacity(nibbles.len() / 2);
                 for byte in nibbles.chunks_exact(2) {
@@ -54,7 +113,7 @@ fn hex_to_u16(hex: &str) -> Option<u16> {
         return None;
     }
     let mut value = 0x0000_u16;
-    for (shift, nibble) in hex.bytes().rev().map(|c| hex_to_u8(c)).enumerate() {
+    for (shift, nibble) in hex.bytes().rev().map(hex_to_u8).enumerate() {
         if nibble > 15 {
             return None;
         }
@@ -66,9 +125,55 @@ fn hex_to_u16(hex: &str) -> Option<u16> {
 /// Note: returns `16` on invalid input
 fn hex_to_u8(c: u8) -> u8 {
     match c {
-        b'0'..=b'9' => (c - b'0'),
+        b'0'..=b'9' => c - b'0',
         b'A'..=b'F' => 10 + c - b'A',
         b'a'..=b'f' => 10 + c - b'a',
         _ => 16,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::MEMORY_SIZE;
+
+    #[test]
+    fn rom_fits_exactly() {
+        let mut mem = Memory::new();
+        let rom = vec![0xAB; MEMORY_SIZE - PROGRAM_START];
+        load_binary(&rom, &mut mem, PROGRAM_START).unwrap();
+        assert_eq!(mem.load_byte(MEMORY_SIZE - 1).unwrap(), 0xAB);
+    }
+
+    #[test]
+    fn rom_too_large() {
+        let mut mem = Memory::new();
+        let rom = vec![0xAB; MEMORY_SIZE - PROGRAM_START + 1];
+        let err = load_binary(&rom, &mut mem, PROGRAM_START).unwrap_err();
+        assert!(matches!(
+            err,
+            MemoryError::RomTooLarge {
+                rom_size: 0xE01,
+                available: 0xE00
+            }
+        ));
+        assert_eq!(mem.load_byte(PROGRAM_START).unwrap(), 0);
+    }
+
+    #[test]
+    fn empty_rom_at_end_of_memory() {
+        let mut mem = Memory::new();
+        load_binary(&[], &mut mem, MEMORY_SIZE).unwrap();
+        assert!(load_binary(&[], &mut mem, MEMORY_SIZE + 1).is_err());
+    }
+
+    #[test]
+    fn entry_point() {
+        let mut mem = Memory::new();
+        load_firmware(&mut mem).unwrap();
+        assert_eq!(mem.load_u16(0x000).unwrap(), 0x1200);
+        set_entry_point(&mut mem, ETI_660_PROGRAM_START).unwrap();
+        assert_eq!(mem.load_u16(0x000).unwrap(), 0x1600);
+        assert!(set_entry_point(&mut mem, 0x1000).is_err());
+    }
+}
@@ -0,0 +1,233 @@
+//! Behaviours in which CHIP-8 interpreters disagree.
+//!
+//! The information used here is taken from
+//! https://github.com/Timendus/chip8-test-suite#quirks-test and
+//! https://chip-8.github.io/extensions/
+
+/// What `STREGS VX`/`LDREGS VX` do to `I` afterwards.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IndexIncrement {
+    /// `I` is left unchanged
+    Unchanged,
+    /// `I <- I + X`
+    ByX,
+    /// `I <- I + X + 1`
+    ByXPlusOne,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quirks {
+    /// `SHR VX VY`/`SHL VX VY` shift `VY` into `VX` (rather than shifting `VX` in place)
+    pub shift_uses_vy: bool,
+    /// How `STREGS VX`/`LDREGS VX` leave `I`
+    pub load_store_index: IndexIncrement,
+    /// `JPV0 XNN` jumps to `XNN + VX` (rather than `XNN + V0`)
+    pub jump_uses_vx: bool,
+    /// `DRW` clips sprites at the edges of the screen (rather than wrapping them around)
+    pub clip_sprites: bool,
+    /// `OR`/`AND`/`XOR` reset `VF` to 0
+    pub logic_resets_vf: bool,
+    /// `DRW` waits for the vertical blank, i.e. at most one sprite is drawn per frame
+    pub display_wait: bool,
+    /// `ADDI VX` sets `VF` to 1 if `I` goes past the end of memory, 0 otherwise
+    pub index_overflow_sets_vf: bool,
+    /// In high resolution, `DRW` sets `VF` to the number of sprite rows that
+    /// collided or were clipped at the bottom (rather than to 1 on any collision)
+    pub count_collisions: bool,
+    /// `F000 NNNN` is the two word XO-CHIP `LDL I NNNN` (rather than `HALT`)
+    pub long_index_load: bool,
+    /// `LDK VX` completes as soon as a key is pressed (rather than when it is released)
+    pub key_wait_on_press: bool,
+}
+
+impl Quirks {
+    /// The original interpreter on the RCA COSMAC VIP
+    pub const COSMAC_VIP: Quirks = Quirks {
+        shift_uses_vy: true,
+        load_store_index: IndexIncrement::ByXPlusOne,
+        jump_uses_vx: false,
+        clip_sprites: true,
+        logic_resets_vf: true,
+        display_wait: true,
+        index_overflow_sets_vf: false,
+        count_collisions: false,
+        long_index_load: false,
+        key_wait_on_press: false,
+    };
+
+    /// CHIP-48 on the HP-48 calculators
+    pub const CHIP_48: Quirks = Quirks {
+        shift_uses_vy: false,
+        load_store_index: IndexIncrement::ByX,
+        jump_uses_vx: true,
+        clip_sprites: true,
+        logic_resets_vf: false,
+        display_wait: false,
+        index_overflow_sets_vf: false,
+        count_collisions: false,
+        long_index_load: false,
+        key_wait_on_press: false,
+    };
+
+    /// SUPER-CHIP 1.1
+    pub const SUPER_CHIP: Quirks = Quirks {
+        shift_uses_vy: false,
+        load_store_index: IndexIncrement::Unchanged,
+        jump_uses_vx: true,
+        clip_sprites: true,
+        logic_resets_vf: false,
+        display_wait: false,
+        index_overflow_sets_vf: false,
+        count_collisions: true,
+        long_index_load: false,
+        key_wait_on_press: false,
+    };
+
+    /// XO-CHIP as implemented by Octo
+    pub const XO_CHIP: Quirks = Quirks {
+        shift_uses_vy: true,
+        load_store_index: IndexIncrement::ByXPlusOne,
+        jump_uses_vx: false,
+        clip_sprites: false,
+        logic_resets_vf: false,
+        display_wait: false,
+        index_overflow_sets_vf: false,
+        count_collisions: false,
+        long_index_load: true,
+        key_wait_on_press: false,
+    };
+
+    /// Names accepted by [`Quirks::preset`]
+    pub const PRESETS: [&'static str; 4] = ["vip", "chip48", "schip", "xochip"];
+
+    /// Looks up a preset by name (see [`Quirks::PRESETS`]).
+    pub fn preset(name: &str) -> Option<Quirks> {
+        match name.to_ascii_lowercase().as_str() {
+            "vip" | "cosmac-vip" | "chip8" => Some(Self::COSMAC_VIP),
+            "chip48" | "chip-48" => Some(Self::CHIP_48),
+            "schip" | "super-chip" | "superchip" => Some(Self::SUPER_CHIP),
+            "xochip" | "xo-chip" => Some(Self::XO_CHIP),
+            _ => None,
+        }
+    }
+}
+
+/// Cassowary's historical behaviour: every quirk off, sprites wrap around.
+impl Default for Quirks {
+    fn default() -> Self {
+        Self {
+            shift_uses_vy: false,
+            load_store_index: IndexIncrement::Unchanged,
+            jump_uses_vx: false,
+            clip_sprites: false,
+            logic_resets_vf: false,
+            display_wait: false,
+            index_overflow_sets_vf: false,
+            count_collisions: false,
+            long_index_load: false,
+            key_wait_on_press: false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::system_with;
+    use crate::{Config, System};
+
+    const PRESETS: [Quirks; 4] = [
+        Quirks::COSMAC_VIP,
+        Quirks::CHIP_48,
+        Quirks::SUPER_CHIP,
+        Quirks::XO_CHIP,
+    ];
+
+    /// Runs the program with `quirks` until it halts.
+    fn run(quirks: Quirks, program: &str) -> System {
+        let config = Config {
+            quirks,
+            ..Config::default()
+        };
+        let mut system = system_with(config, program);
+        system.run().unwrap();
+        system
+    }
+
+    #[test]
+    fn shift_source() {
+        for quirks in PRESETS {
+            // LD V0 0x10; LD V1 0x81; SHR V0 V1; HALT
+            let system = run(quirks, "6010 6181 8016 0000");
+            let expected = if quirks.shift_uses_vy { 0x40 } else { 0x08 };
+            assert_eq!(system.cpu().get_register(0), expected, "{:?}", quirks);
+        }
+    }
+
+    #[test]
+    fn load_store_index() {
+        for quirks in PRESETS {
+            // LD I 0x300; STREGS V2; HALT
+            let system = run(quirks, "A300 F255 0000");
+            let expected = match quirks.load_store_index {
+                IndexIncrement::Unchanged => 0x300,
+                IndexIncrement::ByX => 0x302,
+                IndexIncrement::ByXPlusOne => 0x303,
+            };
+            assert_eq!(system.cpu().index(), expected, "{:?}", quirks);
+        }
+    }
+
+    #[test]
+    fn jump_offset_register() {
+        for quirks in PRESETS {
+            // LD V0 0x10; LD V2 0x20; JPV0 0x220, with HALT at 0x230 and 0x240
+            let system = run(quirks, "6010 6220 B220");
+            let expected = if quirks.jump_uses_vx { 0x242 } else { 0x232 };
+            assert_eq!(system.cpu().pc(), expected, "{:?}", quirks);
+        }
+    }
+
+    #[test]
+    fn logic_resets_vf() {
+        for quirks in PRESETS {
+            // LD VF 5; OR V0 V1; HALT
+            let system = run(quirks, "6F05 8011 0000");
+            let expected = if quirks.logic_resets_vf { 0 } else { 5 };
+            assert_eq!(system.cpu().get_register(0xF), expected, "{:?}", quirks);
+        }
+    }
+
+    #[test]
+    fn clip_or_wrap_sprites() {
+        for quirks in PRESETS {
+            // LD V0 60; LD V1 31; LD I line; DRW V0 V1 2; HALT; line: db 0xFF, 0xFF
+            let system = run(quirks, "603C 611F A20A D012 0000 FFFF");
+            let display = &system.display;
+            assert_eq!(display.pixel(63, 31), 1, "{:?}", quirks);
+            let wrapped = !quirks.clip_sprites as u8;
+            assert_eq!(display.pixel(0, 31), wrapped, "{:?}", quirks);
+            assert_eq!(display.pixel(60, 0), wrapped, "{:?}", quirks);
+        }
+    }
+
+    #[test]
+    fn collision_count() {
+        for quirks in PRESETS {
+            // HIGH; LD I square; DRW V0 V0 0; DRW V0 V0 0; LD V1 56; DRW V0 V1 0;
+            // HALT; square: 32 x db 0xFF
+            let program = format!("00FF A20E D000 D000 6138 D010 0000 {}", "FF".repeat(32));
+            let config = Config {
+                quirks,
+                ..Config::default()
+            };
+            let mut system = system_with(config, &program);
+            system.step_n(4).unwrap();
+            let collided = if quirks.count_collisions { 16 } else { 1 };
+            assert_eq!(system.cpu().get_register(0xF), collided, "{:?}", quirks);
+            system.step_n(2).unwrap();
+            let clipped = if quirks.count_collisions { 8 } else { 0 };
+            assert_eq!(system.cpu().get_register(0xF), clipped, "{:?}", quirks);
+        }
+    }
+}
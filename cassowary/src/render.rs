@@ -0,0 +1,234 @@
+//! Showing the display, see [`System::set_renderer`](crate::System::set_renderer).
+//!
+//! The system presents the [`Display`] to its renderer at the end of every
+//! 60 Hz frame and once more when the CPU halts.
+
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+
+use crate::display::Display;
+
+pub trait Renderer: Send {
+    /// Called at the end of a frame; `changed` tells whether any pixel
+    /// changed since the previous call.
+    fn present(&mut self, display: &Display, changed: bool);
+
+    /// Writes out any buffered output, returning the first error that occurred.
+    fn finish(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Shows nothing.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NullRenderer;
+
+impl Renderer for NullRenderer {
+    fn present(&mut self, _display: &Display, _changed: bool) {}
+}
+
+/// Prints the display as ASCII art whenever it changed during a frame.
+///
+/// Pixels are `' '`, `'*'`, `'+'` and `'#'` for the values 0 to 3.
+/// Write errors are ignored.
+pub struct AsciiRenderer {
+    out: Box<dyn Write + Send>,
+}
+
+impl AsciiRenderer {
+    pub fn new(out: impl Write + Send + 'static) -> Self {
+        Self { out: Box::new(out) }
+    }
+}
+
+impl Renderer for AsciiRenderer {
+    fn present(&mut self, display: &Display, changed: bool) {
+        if changed {
+            let _ = self.out.write_all(ascii_art(display).as_bytes());
+            let _ = self.out.flush();
+        }
+    }
+}
+
+/// The display framed by a border, one line per row.
+pub fn ascii_art(display: &Display) -> String {
+    let border = "-".repeat(display.width());
+    let mut art = format!("/{}\\\n", border);
+    for row in 0..display.height() {
+        art.push('|');
+        for &pixel in display.row(row) {
+            art.push([' ', '*', '+', '#'][pixel as usize & 0x03]);
+        }
+        art.push_str("|\n");
+    }
+    art.push_str(&format!("\\{}/\n", border));
+    art
+}
+
+/// A copy of the display's visible pixels.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub width: usize,
+    pub height: usize,
+    /// row by row, one bit per bitplane
+    pub pixels: Vec<u8>,
+}
+
+impl Frame {
+    pub fn capture(display: &Display) -> Self {
+        Self {
+            width: display.width(),
+            height: display.height(),
+            pixels: (0..display.height())
+                .flat_map(|row| display.row(row).iter().copied())
+                .collect(),
+        }
+    }
+
+    pub fn pixel(&self, col: usize, row: usize) -> u8 {
+        self.pixels[row * self.width + col]
+    }
+}
+
+/// Keeps the last frame presented, for tests and tools.
+///
+/// Clones share the same frame, so one clone can be given to the system and
+/// another kept to look at the result.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryRenderer {
+    shared: Arc<Mutex<(Option<Frame>, u64)>>,
+}
+
+impl MemoryRenderer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The last frame presented, if any.
+    pub fn frame(&self) -> Option<Frame> {
+        self.shared.lock().unwrap().0.clone()
+    }
+
+    /// Number of frames presented so far.
+    pub fn count(&self) -> u64 {
+        self.shared.lock().unwrap().1
+    }
+}
+
+impl Renderer for MemoryRenderer {
+    fn present(&mut self, display: &Display, changed: bool) {
+        let mut shared = self.shared.lock().unwrap();
+        if changed || shared.0.is_none() {
+            shared.0 = Some(Frame::capture(display));
+        }
+        shared.1 += 1;
+    }
+}
+
+/// Presents to two renderers, e.g. to record what is being shown.
+pub struct Tee {
+    first: Box<dyn Renderer>,
+    second: Box<dyn Renderer>,
+}
+
+impl Tee {
+    pub fn new(first: Box<dyn Renderer>, second: Box<dyn Renderer>) -> Self {
+        Self { first, second }
+    }
+}
+
+impl Renderer for Tee {
+    fn present(&mut self, display: &Display, changed: bool) {
+        self.first.present(display, changed);
+        self.second.present(display, changed);
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        let first = self.first.finish();
+        let second = self.second.finish();
+        first.and(second)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::assembled;
+    use crate::System;
+
+    /// Draws the font's `0` at (1, 2) and halts.
+    const ZERO: &str = "LD V0 1\nLD V1 2\nLD V2 0\nLDSPR V2\nDRW V0 V1 5\nHALT";
+
+    fn zero() -> System {
+        let mut system = assembled(ZERO);
+        system.run().unwrap();
+        system
+    }
+
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn ascii_art_frames_the_display() {
+        let art = ascii_art(zero().display());
+        let lines: Vec<&str> = art.lines().collect();
+        assert_eq!(lines.len(), 32 + 2);
+        assert_eq!(lines[0], format!("/{}\\", "-".repeat(64)));
+        assert_eq!(lines[1], format!("|{}|", " ".repeat(64)));
+        assert_eq!(lines[3], format!("| ****{}|", " ".repeat(59)));
+        assert_eq!(lines[4], format!("| *  *{}|", " ".repeat(59)));
+        assert_eq!(lines[33], format!("\\{}/", "-".repeat(64)));
+    }
+
+    #[test]
+    fn ascii_renderer_prints_changed_frames() {
+        let system = zero();
+        let out = Shared::default();
+        let mut renderer = AsciiRenderer::new(out.clone());
+        renderer.present(system.display(), false);
+        assert!(out.0.lock().unwrap().is_empty());
+        renderer.present(system.display(), true);
+        let printed = String::from_utf8(out.0.lock().unwrap().clone()).unwrap();
+        assert_eq!(printed, ascii_art(system.display()));
+    }
+
+    #[test]
+    fn memory_renderer_keeps_the_last_frame() {
+        let renderer = MemoryRenderer::new();
+        assert_eq!(renderer.frame(), None);
+        let mut system = assembled(ZERO);
+        system.set_renderer(Box::new(renderer.clone()));
+        system.run().unwrap();
+        // presented once, when the CPU halted within the first frame
+        assert_eq!(renderer.count(), 1);
+        let frame = renderer.frame().unwrap();
+        assert_eq!((frame.width, frame.height), (64, 32));
+        assert_eq!(frame, Frame::capture(system.display()));
+        assert_eq!(frame.pixel(1, 2), 1);
+        assert_eq!(frame.pixel(2, 3), 0);
+    }
+
+    #[test]
+    fn tee_presents_to_both() {
+        let (first, second) = (MemoryRenderer::new(), MemoryRenderer::new());
+        let mut tee = Tee::new(Box::new(first.clone()), Box::new(second.clone()));
+        let system = zero();
+        tee.present(system.display(), true);
+        tee.present(system.display(), false);
+        tee.finish().unwrap();
+        for renderer in [first, second] {
+            assert_eq!(renderer.count(), 2);
+            assert_eq!(renderer.frame(), Some(Frame::capture(system.display())));
+        }
+    }
+}
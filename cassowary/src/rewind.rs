@@ -0,0 +1,407 @@
+//! Rewind history, see [`System::rewind_instructions`](crate::System::rewind_instructions).
+//!
+//! The system records a [save state](crate::state) at the start of every
+//! frame. Only the newest one is kept whole; each older one is stored as the
+//! difference to the next newer one (XORed and run-length encoded), which is
+//! small as few bytes change per frame. The oldest frames are dropped when the
+//! history exceeds its memory budget.
+//!
+//! Going back to a point inside a frame restores the state at the start of
+//! the frame and executes up to that point again, pressing and releasing keys
+//! where they were. Nothing is traced, presented or played meanwhile. This is
+//! exact as long as the timers are emulated and the random source can be
+//! saved.
+
+use std::collections::VecDeque;
+
+use thiserror::Error;
+
+use crate::cpu::CpuError;
+use crate::state::StateError;
+
+/// Default memory budget of the rewind history (16 MiB)
+pub const DEFAULT_REWIND_BUDGET: usize = 16 << 20;
+
+#[derive(Error, Debug)]
+pub enum RewindError {
+    #[error("rewind is not enabled")]
+    NotEnabled,
+    #[error("cannot go back further than instruction {oldest}")]
+    TooFar { oldest: u64 },
+    #[error("error restoring state: {0}")]
+    State(#[from] StateError),
+    #[error("error executing up to the target instruction: {0}")]
+    Cpu(#[from] CpuError),
+}
+
+/// Position of a snapshot in the execution.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Mark {
+    /// instructions executed
+    pub cycles: u64,
+    /// frames completed
+    pub frame: u64,
+}
+
+/// A key pressed or released before executing instruction `cycles`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct KeyChange {
+    pub cycles: u64,
+    pub key: u8,
+    pub down: bool,
+}
+
+struct Delta {
+    mark: Mark,
+    /// XOR with the next newer state, run-length encoded
+    data: Vec<u8>,
+}
+
+pub struct Rewind {
+    budget: usize,
+    latest: Option<(Mark, Vec<u8>)>,
+    /// oldest first; the last one turns `latest` into the state before it
+    deltas: VecDeque<Delta>,
+    used: usize,
+    /// key changes since the oldest snapshot, oldest first
+    keys: Vec<KeyChange>,
+}
+
+impl Rewind {
+    /// Creates an empty history using at most about `budget` bytes.
+    ///
+    /// The newest snapshot is always kept, even if it alone exceeds the budget.
+    pub fn new(budget: usize) -> Self {
+        Self {
+            budget,
+            latest: None,
+            deltas: VecDeque::new(),
+            used: 0,
+            keys: Vec::new(),
+        }
+    }
+
+    pub fn budget(&self) -> usize {
+        self.budget
+    }
+
+    /// Bytes currently used by the snapshots.
+    pub fn used(&self) -> usize {
+        self.used
+    }
+
+    /// Number of snapshots kept.
+    pub fn len(&self) -> usize {
+        self.deltas.len() + self.latest.is_some() as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.latest.is_none()
+    }
+
+    /// Instruction count of the oldest snapshot.
+    pub fn oldest(&self) -> Option<u64> {
+        match self.deltas.front() {
+            Some(delta) => Some(delta.mark.cycles),
+            None => self.latest.as_ref().map(|(mark, _)| mark.cycles),
+        }
+    }
+
+    pub(crate) fn push(&mut self, mark: Mark, state: Vec<u8>) {
+        if let Some((prev_mark, prev)) = self.latest.take() {
+            self.used -= prev.len();
+            if prev.len() == state.len() {
+                let data = encode_delta(&prev, &state);
+                self.used += data.len();
+                self.deltas.push_back(Delta {
+                    mark: prev_mark,
+                    data,
+                });
+            } else {
+                self.deltas.clear();
+                self.used = 0;
+            }
+        }
+        self.used += state.len();
+        self.latest = Some((mark, state));
+        while self.used > self.budget {
+            let Some(oldest) = self.deltas.pop_front() else {
+                break;
+            };
+            self.used -= oldest.data.len();
+        }
+        if let Some(oldest) = self.oldest() {
+            self.keys.retain(|change| change.cycles >= oldest);
+        }
+    }
+
+    /// Records a key change, which must come after the newest snapshot.
+    pub(crate) fn push_key(&mut self, change: KeyChange) {
+        self.keys.push(change);
+    }
+
+    /// Drops snapshots and key changes newer than `cycles`, returning the
+    /// newest remaining snapshot and the key changes after it.
+    pub(crate) fn back_to(&mut self, cycles: u64) -> Option<(Mark, &[u8], &[KeyChange])> {
+        self.keys.retain(|change| change.cycles < cycles);
+        loop {
+            let (mark, _) = self.latest.as_ref()?;
+            if mark.cycles <= cycles {
+                break;
+            }
+            let Some(delta) = self.deltas.pop_back() else {
+                self.latest = None;
+                self.used = 0;
+                self.keys.clear();
+                return None;
+            };
+            let (_, state) = self.latest.as_mut().unwrap();
+            decode_delta(&delta.data, state);
+            self.used -= delta.data.len();
+            self.latest.as_mut().unwrap().0 = delta.mark;
+        }
+        let (mark, state) = self.latest.as_ref()?;
+        let after = self
+            .keys
+            .partition_point(|change| change.cycles < mark.cycles);
+        Some((*mark, state.as_slice(), &self.keys[after..]))
+    }
+
+    /// Instruction count at the start of `frame`, if it is still in the history.
+    pub(crate) fn frame_start(&self, frame: u64) -> Option<u64> {
+        let latest = self.latest.as_ref().map(|(mark, _)| mark);
+        self.deltas
+            .iter()
+            .map(|delta| &delta.mark)
+            .chain(latest)
+            .find(|mark| mark.frame == frame)
+            .map(|mark| mark.cycles)
+    }
+}
+
+/// Encodes `old ^ new` as runs of: number of zero bytes, number of literal
+/// bytes (both LEB128) and the literal bytes.
+fn encode_delta(old: &[u8], new: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut xored = old.iter().zip(new).map(|(a, b)| a ^ b).peekable();
+    let mut literal = Vec::new();
+    while xored.peek().is_some() {
+        let mut zeros = 0;
+        while xored.next_if_eq(&0).is_some() {
+            zeros += 1;
+        }
+        literal.clear();
+        while let Some(byte) = xored.next_if(|byte| *byte != 0) {
+            literal.push(byte);
+        }
+        write_len(&mut out, zeros);
+        write_len(&mut out, literal.len());
+        out.extend_from_slice(&literal);
+    }
+    out
+}
+
+/// XORs the delta into `state`.
+fn decode_delta(mut delta: &[u8], state: &mut [u8]) {
+    let mut pos = 0;
+    while !delta.is_empty() {
+        pos += read_len(&mut delta);
+        let len = read_len(&mut delta);
+        for (byte, change) in state[pos..(pos + len)].iter_mut().zip(&delta[..len]) {
+            *byte ^= change;
+        }
+        delta = &delta[len..];
+        pos += len;
+    }
+}
+
+fn write_len(out: &mut Vec<u8>, mut len: usize) {
+    while len >= 0x80 {
+        out.push((len as u8) | 0x80);
+        len >>= 7;
+    }
+    out.push(len as u8);
+}
+
+fn read_len(input: &mut &[u8]) -> usize {
+    let mut len = 0;
+    let mut shift = 0;
+    loop {
+        let byte = input[0];
+        *input = &input[1..];
+        len |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return len;
+        }
+        shift += 7;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Write};
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::random::{RandomSource, SeededRandom};
+    use crate::render::MemoryRenderer;
+    use crate::tests::assembled_with;
+    use crate::trace::{TraceFormat, Tracer};
+    use crate::{Config, System};
+
+    const PROGRAM: &str = "
+        loop:   RND V0 0x0F
+                LDSPR V0
+                DRW V1 V2 5
+                ADD V1 3
+                SKP V3
+                ADD V2 1
+                LDDT V4
+                SE V4 0
+                JP loop
+                STDT V1
+                JP loop";
+
+    /// Runs up to instruction `cycles`, holding key 0 from instruction 53
+    /// to 136 and tapping key 5 in the middle of a frame.
+    fn run_to(system: &mut System, cycles: u64) {
+        while system.cycles() < cycles {
+            match system.cycles() {
+                53 => system.key_down(0),
+                77 => system.key_down(5),
+                78 => system.key_up(5),
+                136 => system.key_up(0),
+                _ => {}
+            }
+            system.step().unwrap();
+        }
+    }
+
+    fn rewinding() -> System {
+        let config = Config {
+            rewind_budget: DEFAULT_REWIND_BUDGET,
+            ..Config::default()
+        };
+        assembled_with(config, PROGRAM)
+    }
+
+    #[test]
+    fn rewinding_matches_a_fresh_run() {
+        let mut system = rewinding();
+        for n in [1, 7, 162, 221, 245, 299] {
+            run_to(&mut system, 300);
+            system.rewind_instructions(n).unwrap();
+            assert_eq!(system.cycles(), 300 - n);
+            let mut fresh = assembled_with(Config::default(), PROGRAM);
+            run_to(&mut fresh, 300 - n);
+            assert_eq!(system.save_state(), fresh.save_state(), "back {}", n);
+        }
+    }
+
+    struct Lines(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Lines {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn replaying_is_not_traced_or_presented() {
+        let mut system = rewinding();
+        let trace = Arc::new(Mutex::new(Vec::new()));
+        let frames = MemoryRenderer::new();
+        system.set_tracer(Tracer::new(Lines(Arc::clone(&trace)), TraceFormat::Text));
+        system.set_renderer(Box::new(frames.clone()));
+        let lines = || {
+            trace
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|&&b| b == b'\n')
+                .count()
+        };
+
+        run_to(&mut system, 300);
+        let snapshots = system.rewind().unwrap().len();
+        assert_eq!(lines(), 299);
+        assert_eq!(frames.count(), 30);
+        system.rewind_instructions(95).unwrap();
+        assert_eq!(lines(), 299);
+        assert_eq!(frames.count(), 30);
+        assert_eq!(system.rewind().unwrap().len(), snapshots - 10);
+        system.step().unwrap();
+        assert_eq!(lines(), 300);
+    }
+
+    #[test]
+    fn too_far_back() {
+        let config = Config {
+            rewind_budget: 1,
+            ..Config::default()
+        };
+        let mut system = assembled_with(config, PROGRAM);
+        run_to(&mut system, 55);
+        // only the snapshot at the start of the current frame is kept
+        let oldest = system.rewind().unwrap().oldest().unwrap();
+        assert_eq!(oldest, 50);
+        assert!(matches!(
+            system.rewind_instructions(6),
+            Err(RewindError::TooFar { oldest: 50 })
+        ));
+        assert_eq!(system.cycles(), 55);
+        system.rewind_instructions(5).unwrap();
+        assert_eq!(system.cycles(), 50);
+    }
+
+    fn random_bytes(rng: &mut SeededRandom, len: usize) -> Vec<u8> {
+        (0..len).map(|_| rng.next_byte()).collect()
+    }
+
+    #[test]
+    fn delta_round_trip() {
+        let mut rng = SeededRandom::new(16);
+        for len in [0, 1, 100, 1000] {
+            let old = random_bytes(&mut rng, len);
+            // unrelated contents, and sparse changes with long runs between them
+            let mut sparse = old.clone();
+            for byte in sparse.iter_mut().step_by(300) {
+                *byte ^= 0x5A;
+            }
+            for new in [random_bytes(&mut rng, len), sparse, old.clone()] {
+                let delta = encode_delta(&old, &new);
+                let mut state = new.clone();
+                decode_delta(&delta, &mut state);
+                assert_eq!(state, old);
+            }
+        }
+    }
+
+    #[test]
+    fn oldest_snapshots_are_evicted() {
+        let mut rng = SeededRandom::new(17);
+        let mut rewind = Rewind::new(100);
+        let mut states = Vec::new();
+        for frame in 0..10 {
+            let state = random_bytes(&mut rng, 40);
+            let mark = Mark {
+                cycles: frame * 10,
+                frame,
+            };
+            rewind.push(mark, state.clone());
+            states.push(state);
+            assert!(rewind.used() <= 100 || rewind.len() == 1);
+        }
+        // random states barely compress: the newest and a single delta fit
+        assert_eq!(rewind.len(), 2);
+        assert_eq!(rewind.oldest(), Some(80));
+        let (mark, state, _) = rewind.back_to(85).unwrap();
+        assert_eq!(mark.frame, 8);
+        assert_eq!(state, states[8]);
+        assert!(rewind.back_to(79).is_none());
+    }
+}
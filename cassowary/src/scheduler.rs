@@ -0,0 +1,173 @@
+//! Running frames at the pace of real time.
+//!
+//! [`System::run_frame`] executes a frame as fast as the host allows; the
+//! [`Scheduler`] sleeps between frames so that they complete 60 times per
+//! second, scaled by its speed. As emulated timers tick and the display is
+//! presented at the end of each frame, they follow the same pace.
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+use thiserror::Error;
+
+use crate::{CpuError, RunSummary, System};
+
+/// Frames per second at normal speed
+pub const FRAME_RATE: u32 = 60;
+
+/// Slowest speed relative to real time
+pub const MIN_SPEED: f64 = 0.01;
+/// Fastest speed relative to real time, short of [`f64::INFINITY`]
+pub const MAX_SPEED: f64 = 100.0;
+
+/// Duration of a frame at normal speed
+const FRAME: Duration = Duration::from_nanos(1_000_000_000 / FRAME_RATE as u64);
+
+#[derive(Error, Debug, Clone, Copy, PartialEq)]
+#[error("speed must be infinite or from {MIN_SPEED} to {MAX_SPEED}, got {0}")]
+pub struct InvalidSpeed(pub f64);
+
+#[derive(Debug, Clone)]
+pub struct Scheduler {
+    speed: f64,
+    paused: bool,
+    /// when the previous frame was due
+    last: Option<Instant>,
+}
+
+impl Default for Scheduler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Scheduler {
+    /// Creates a scheduler running at normal speed.
+    pub fn new() -> Self {
+        Self {
+            speed: 1.0,
+            paused: false,
+            last: None,
+        }
+    }
+
+    /// Speed relative to real time.
+    pub fn speed(&self) -> f64 {
+        self.speed
+    }
+
+    /// Sets the speed relative to real time: above 1 fast-forwards, below 1
+    /// is slow motion and [`f64::INFINITY`] runs as fast as the host allows.
+    /// Other speeds, outside [`MIN_SPEED`]`..=`[`MAX_SPEED`], are rejected.
+    pub fn set_speed(&mut self, speed: f64) -> Result<(), InvalidSpeed> {
+        if !(MIN_SPEED..=MAX_SPEED).contains(&speed) && speed != f64::INFINITY {
+            return Err(InvalidSpeed(speed));
+        }
+        self.speed = speed;
+        Ok(())
+    }
+
+    pub fn pause(&mut self) {
+        self.paused = true;
+    }
+
+    pub fn resume(&mut self) {
+        self.paused = false;
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Waits until the next frame is due, then runs it unless paused.
+    ///
+    /// While paused nothing runs, but the call still waits for a frame at
+    /// normal speed, so a frontend calling it in a loop keeps reading input.
+    pub fn run_frame(&mut self, system: &mut System) -> Result<Option<RunSummary>, CpuError> {
+        self.wait();
+        if self.paused {
+            return Ok(None);
+        }
+        system.run_frame().map(Some)
+    }
+
+    /// Runs frame after frame until the CPU halts, or until it waits for a
+    /// key press while no movie is replayed, as no key can arrive then.
+    /// Returns the summary of the last frame.
+    pub fn run(&mut self, system: &mut System) -> Result<RunSummary, CpuError> {
+        loop {
+            let Some(summary) = self.run_frame(system)? else {
+                continue;
+            };
+            if summary.halted || (summary.awaiting_key && !system.is_playing_movie()) {
+                return Ok(summary);
+            }
+        }
+    }
+
+    fn frame_time(&self) -> Duration {
+        if self.paused {
+            FRAME
+        } else {
+            FRAME.div_f64(self.speed)
+        }
+    }
+
+    fn wait(&mut self) {
+        let now = Instant::now();
+        let due = self.last.map_or(now, |last| last + self.frame_time());
+        if due > now {
+            thread::sleep(due - now);
+        }
+        // too slow to keep up: carry on from now rather than rushing
+        self.last = Some(due.max(now));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::assembled;
+
+    fn fastest() -> Scheduler {
+        let mut scheduler = Scheduler::new();
+        scheduler.set_speed(f64::INFINITY).unwrap();
+        scheduler
+    }
+
+    #[test]
+    fn run_stops_when_halted() {
+        let mut system = assembled("LD V0 1\nHALT");
+        let summary = fastest().run(&mut system).unwrap();
+        assert!(summary.halted);
+    }
+
+    #[test]
+    fn run_stops_waiting_for_a_key() {
+        let mut system = assembled("LDK V0\nHALT");
+        let summary = fastest().run(&mut system).unwrap();
+        assert!(summary.awaiting_key && !summary.halted);
+        assert!(system.cpu().is_awaiting_key());
+    }
+
+    #[test]
+    fn speeds_are_bounded() {
+        let mut scheduler = Scheduler::new();
+        for speed in [MIN_SPEED, MAX_SPEED, f64::INFINITY] {
+            scheduler.set_speed(speed).unwrap();
+            assert_eq!(scheduler.speed(), speed);
+        }
+        for speed in [
+            1e-20,
+            MAX_SPEED * 2.0,
+            0.0,
+            -1.0,
+            f64::NEG_INFINITY,
+            f64::NAN,
+        ] {
+            let err = scheduler.set_speed(speed).unwrap_err();
+            assert_eq!(err.0.to_bits(), speed.to_bits());
+            assert_eq!(scheduler.speed(), f64::INFINITY);
+        }
+    }
+}
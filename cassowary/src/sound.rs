@@ -1,13 +1,16 @@
-use std::time::Duration;
-use std::{sync::Arc, thread};
+use std::sync::{Arc, Mutex};
+use std::thread;
 
 use crossbeam_channel::unbounded;
-use rodio::{self, source::SineWave};
 use thiserror::Error;
 
-use crate::timer::Timer;
+use crate::audio::{AudioBackend, NullAudio};
+use crate::timer::{Timer, TimerMode};
 
-const TIMER_TICK: Duration = Duration::from_millis(16);
+/// Size of the XO-CHIP audio pattern buffer in bytes
+pub const AUDIO_PATTERN_LEN: usize = 16;
+/// Pitch at which an audio pattern plays back at 4000 samples per second
+pub const DEFAULT_PITCH: u8 = 64;
 
 #[derive(Error, Debug)]
 pub enum SoundError {
@@ -15,48 +18,152 @@ pub enum SoundError {
     SetupError(String),
 }
 
+/// XO-CHIP audio pattern: 128 one bit samples, most significant bit first,
+/// played back in a loop at a rate set by the pitch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AudioPattern {
+    pub bits: [u8; AUDIO_PATTERN_LEN],
+    pub pitch: u8,
+}
+
+impl AudioPattern {
+    /// Playback rate in samples (bits) per second
+    pub fn playback_rate(&self) -> f32 {
+        4000.0 * 2f32.powf((self.pitch as f32 - 64.0) / 48.0)
+    }
+
+    /// Value of the `n`th bit of the pattern (wrapping around)
+    pub fn bit(&self, n: usize) -> bool {
+        let n = n % (AUDIO_PATTERN_LEN * 8);
+        self.bits[n / 8] & (0x80 >> (n % 8)) != 0
+    }
+}
+
 pub struct SoundSystem {
-    timer: Arc<Timer>,
+    timer: Timer,
+    pitch: u8,
+    pattern: Option<AudioPattern>,
+    /// shared with the thread following a real-time timer
+    output: Arc<Mutex<Output>>,
+}
+
+/// The backend and whether it was last told to play.
+struct Output {
+    backend: Box<dyn AudioBackend>,
+    playing: bool,
+}
+
+impl Output {
+    fn update(&mut self, ticks: u8) {
+        let playing = ticks > 0;
+        if playing != self.playing {
+            self.playing = playing;
+            self.backend.set_playing(playing);
+        }
+    }
 }
 
 impl SoundSystem {
-    pub fn start_new() -> Result<Self, SoundError> {
-        let tone = setup_tone(440)?;
-        let (changed_tx, changed_rx) = unbounded();
-        thread::spawn(move || {
-            let mut playing = false;
-            for change in changed_rx {
-                if change == 0 {
-                    if playing {
-                        tone.pause();
-                        playing = false;
-                        println!("BEEP.end.");
+    /// Creates a sound system playing nothing (see [`SoundSystem::set_backend`]).
+    pub fn new(mode: TimerMode) -> Self {
+        let output = Arc::new(Mutex::new(Output {
+            backend: Box::new(NullAudio),
+            playing: false,
+        }));
+        let timer = match mode {
+            // updated synchronously, so frames render the same every run
+            TimerMode::Emulated => Timer::new(mode, None),
+            TimerMode::RealTime => {
+                let (changed_tx, changed_rx) = unbounded();
+                let output = Arc::clone(&output);
+                thread::spawn(move || {
+                    for ticks in changed_rx {
+                        output.lock().unwrap().update(ticks);
                     }
-                } else {
-                    if !playing {
-                        tone.play();
-                        playing = true;
-                        println!("BEEP.start.");
-                    }
-                }
+                });
+                Timer::new(mode, Some(changed_tx))
             }
-        });
-        let timer = Timer::start_new(TIMER_TICK, Some(changed_tx));
-        Ok(Self { timer })
+        };
+        Self {
+            timer,
+            pitch: DEFAULT_PITCH,
+            pattern: None,
+            output,
+        }
+    }
+
+    /// Replaces the audio backend, returning the previous one.
+    pub fn set_backend(&mut self, mut backend: Box<dyn AudioBackend>) -> Box<dyn AudioBackend> {
+        let mut output = self.output.lock().unwrap();
+        backend.set_pattern(self.pattern);
+        backend.set_playing(output.playing);
+        let mut old = std::mem::replace(&mut output.backend, backend);
+        old.set_playing(false);
+        old
+    }
+
+    fn update_output(&mut self) {
+        if let Timer::Emulated { .. } = self.timer {
+            self.output.lock().unwrap().update(self.timer.get());
+        }
     }
 
     pub fn set_timer(&mut self, value: u8) {
         self.timer.set(value);
+        self.update_output();
     }
-}
 
-fn setup_tone(tone_hz: u32) -> Result<rodio::Sink, SoundError> {
-    let (_stream, stream_handle) = rodio::OutputStream::try_default()
-        .map_err(|err| SoundError::SetupError(err.to_string()))?;
-    let sink = rodio::Sink::try_new(&stream_handle)
-        .map_err(|err| SoundError::SetupError(err.to_string()))?;
-    sink.pause();
-    sink.append(SineWave::new(tone_hz));
-    sink.set_volume(0.9);
-    Ok(sink)
+    pub fn get_timer(&self) -> u8 {
+        self.timer.get()
+    }
+
+    /// Ends an emulated frame: the backend plays it, then the sound timer
+    /// advances (see [`TimerMode::Emulated`]).
+    pub fn tick(&mut self) {
+        self.output.lock().unwrap().backend.frame();
+        self.timer.tick();
+        self.update_output();
+    }
+
+    /// Advances the sound timer like [`SoundSystem::tick`], but without the
+    /// backend playing the frame (e.g. while replaying up to a rewind target).
+    pub(crate) fn skip_frame(&mut self) {
+        self.timer.tick();
+        self.update_output();
+    }
+
+    /// The XO-CHIP audio pattern, or `None` while the plain buzzer tone is used
+    pub fn pattern(&self) -> Option<AudioPattern> {
+        self.pattern
+    }
+
+    pub fn set_pattern(&mut self, bits: [u8; AUDIO_PATTERN_LEN]) {
+        self.update_pattern(Some(AudioPattern {
+            bits,
+            pitch: self.pitch,
+        }));
+    }
+
+    pub fn pitch(&self) -> u8 {
+        self.pitch
+    }
+
+    /// Restores the pitch and pattern (see [`crate::state`]).
+    pub(crate) fn restore_audio(&mut self, pitch: u8, bits: Option<[u8; AUDIO_PATTERN_LEN]>) {
+        self.pitch = pitch;
+        self.update_pattern(bits.map(|bits| AudioPattern { bits, pitch }));
+    }
+
+    pub fn set_pitch(&mut self, pitch: u8) {
+        self.pitch = pitch;
+        let pattern = self
+            .pattern
+            .map(|pattern| AudioPattern { pitch, ..pattern });
+        self.update_pattern(pattern);
+    }
+
+    fn update_pattern(&mut self, pattern: Option<AudioPattern>) {
+        self.pattern = pattern;
+        self.output.lock().unwrap().backend.set_pattern(pattern);
+    }
 }
@@ -0,0 +1,458 @@
+//! Save states, see [`System::save_state`](crate::System::save_state).
+//!
+//! A save state is a small header, the payload and a checksum. All integers
+//! are little-endian.
+//!
+//! | Size | Field                                  |
+//! |------|----------------------------------------|
+//! | 4    | magic: `C8SS`                          |
+//! | 2    | format version: [`VERSION`]            |
+//! | 4    | payload length `N`                     |
+//! | N    | payload                                |
+//! | 4    | CRC-32 (IEEE) of the payload           |
+//!
+//! Version 1 payload, in order:
+//!
+//! | Size      | Field                                                   |
+//! |-----------|---------------------------------------------------------|
+//! | 8         | instructions executed                                   |
+//! | 8         | frames completed                                        |
+//! | 4         | instructions executed in the current frame              |
+//! | 16        | registers `V0` to `VF`                                  |
+//! | 4         | PC                                                      |
+//! | 4         | `I`                                                     |
+//! | 1         | stack depth (0 to 16)                                   |
+//! | 16 x 4    | stack                                                   |
+//! | 16        | RPL user flags                                          |
+//! | 1         | waiting for a key (0 or 1)                              |
+//! | 1         | halted (0 or 1)                                         |
+//! | 1 + 8     | random generator state, if saved (flag, state)          |
+//! | 1         | delay timer                                             |
+//! | 1         | sound timer                                             |
+//! | 1         | audio pitch                                             |
+//! | 1 + 16    | audio pattern, if set (flag, pattern)                   |
+//! | 2         | keys held down, bit `k` for key `k`                     |
+//! | 1 + 1     | `LDK VX` progress: 0 = no key, 1 = key pressed,         |
+//! |           | 2 = key pressed and released; then the key              |
+//! | 1         | high resolution (0 or 1)                                |
+//! | 1         | selected bitplanes                                      |
+//! | 64 x 128  | pixels, row by row, one bitplane per bit                |
+//! | 4 + size  | memory size and contents                                |
+//!
+//! Later versions will keep loading older ones.
+
+use thiserror::Error;
+
+use crate::display::{HIRES_HEIGHT, HIRES_WIDTH, PLANES};
+use crate::instructions::MemAddr;
+use crate::keyboard::KeyWait;
+use crate::sound::AUDIO_PATTERN_LEN;
+
+const MAGIC: &[u8; 4] = b"C8SS";
+/// Current version of the save state format
+pub const VERSION: u16 = 1;
+const HEADER_LEN: usize = 10;
+const CHECKSUM_LEN: usize = 4;
+
+#[derive(Error, Debug)]
+pub enum StateError {
+    #[error("not a save state")]
+    NotAState,
+    #[error("unsupported save state version {0} (supported up to {VERSION})")]
+    UnsupportedVersion(u16),
+    #[error("corrupt save state: {0}")]
+    Corrupt(&'static str),
+    #[error("save state has {saved} bytes of memory, but the system has {system}")]
+    MemorySizeMismatch { saved: usize, system: usize },
+}
+
+pub(crate) struct CpuState {
+    pub registers: [u8; 16],
+    pub pc: MemAddr,
+    pub index: MemAddr,
+    pub sp: usize,
+    pub stack: [MemAddr; 16],
+    pub flags: [u8; 16],
+    pub awaiting_key: bool,
+    pub halted: bool,
+    pub rng: Option<u64>,
+}
+
+pub(crate) struct DisplayState {
+    pub pixels: [[u8; HIRES_WIDTH]; HIRES_HEIGHT],
+    pub hires: bool,
+    pub planes: u8,
+}
+
+/// Everything in a save state, decoded and validated.
+pub(crate) struct SystemState {
+    pub cycles: u64,
+    pub frame: u64,
+    pub frame_cycles: usize,
+    pub cpu: CpuState,
+    pub delay: u8,
+    pub sound: u8,
+    pub pitch: u8,
+    pub pattern: Option<[u8; AUDIO_PATTERN_LEN]>,
+    pub keys: u16,
+    pub key_wait: KeyWait,
+    pub display: DisplayState,
+    pub memory: Vec<u8>,
+}
+
+impl SystemState {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Writer(Vec::new());
+        out.u64(self.cycles);
+        out.u64(self.frame);
+        out.u32(self.frame_cycles as u32);
+
+        let cpu = &self.cpu;
+        out.bytes(&cpu.registers);
+        out.u32(cpu.pc as u32);
+        out.u32(cpu.index as u32);
+        out.u8(cpu.sp as u8);
+        for addr in cpu.stack {
+            out.u32(addr as u32);
+        }
+        out.bytes(&cpu.flags);
+        out.bool(cpu.awaiting_key);
+        out.bool(cpu.halted);
+        out.bool(cpu.rng.is_some());
+        out.u64(cpu.rng.unwrap_or(0));
+
+        out.u8(self.delay);
+        out.u8(self.sound);
+        out.u8(self.pitch);
+        out.bool(self.pattern.is_some());
+        out.bytes(&self.pattern.unwrap_or_default());
+        out.u16(self.keys);
+        let (progress, key) = match self.key_wait {
+            KeyWait::Idle => (0, 0),
+            KeyWait::Pressed(key) => (1, key),
+            KeyWait::Released(key) => (2, key),
+        };
+        out.u8(progress);
+        out.u8(key);
+
+        out.bool(self.display.hires);
+        out.u8(self.display.planes);
+        for row in &self.display.pixels {
+            out.bytes(row);
+        }
+
+        out.u32(self.memory.len() as u32);
+        out.bytes(&self.memory);
+
+        let payload = out.0;
+        let mut state = Vec::with_capacity(HEADER_LEN + payload.len() + CHECKSUM_LEN);
+        state.extend_from_slice(MAGIC);
+        state.extend_from_slice(&VERSION.to_le_bytes());
+        state.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        state.extend_from_slice(&payload);
+        state.extend_from_slice(&crc32(&payload).to_le_bytes());
+        state
+    }
+
+    /// Decodes a save state for a system with `memory_size` bytes of memory.
+    pub fn decode(state: &[u8], memory_size: usize) -> Result<Self, StateError> {
+        if state.len() < HEADER_LEN || &state[..4] != MAGIC {
+            return Err(StateError::NotAState);
+        }
+        let version = u16::from_le_bytes([state[4], state[5]]);
+        if version == 0 || version > VERSION {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+        let len = u32::from_le_bytes([state[6], state[7], state[8], state[9]]) as usize;
+        if state.len() != HEADER_LEN + len + CHECKSUM_LEN {
+            return Err(StateError::Corrupt("wrong length"));
+        }
+        let payload = &state[HEADER_LEN..(HEADER_LEN + len)];
+        let checksum = &state[(HEADER_LEN + len)..];
+        if crc32(payload).to_le_bytes() != checksum {
+            return Err(StateError::Corrupt("checksum mismatch"));
+        }
+
+        let mut input = Reader(payload);
+        let cycles = input.u64()?;
+        let frame = input.u64()?;
+        let frame_cycles = input.u32()? as usize;
+
+        let registers = input.array()?;
+        let pc = input.u32()? as MemAddr;
+        let index = input.u32()? as MemAddr;
+        let sp = input.u8()? as usize;
+        if sp > 16 {
+            return Err(StateError::Corrupt("stack overflowed"));
+        }
+        let mut stack = [0; 16];
+        for addr in stack.iter_mut() {
+            *addr = input.u32()? as MemAddr;
+        }
+        let flags = input.array()?;
+        let awaiting_key = input.bool()?;
+        let halted = input.bool()?;
+        let has_rng = input.bool()?;
+        let rng = input.u64()?;
+        let cpu = CpuState {
+            registers,
+            pc,
+            index,
+            sp,
+            stack,
+            flags,
+            awaiting_key,
+            halted,
+            rng: has_rng.then_some(rng),
+        };
+
+        let delay = input.u8()?;
+        let sound = input.u8()?;
+        let pitch = input.u8()?;
+        let has_pattern = input.bool()?;
+        let pattern = input.array()?;
+        let keys = input.u16()?;
+        let progress = input.u8()?;
+        let key = input.u8()?;
+        let key_wait = match progress {
+            _ if key > 0xF => return Err(StateError::Corrupt("invalid key")),
+            0 => KeyWait::Idle,
+            1 => KeyWait::Pressed(key),
+            2 => KeyWait::Released(key),
+            _ => return Err(StateError::Corrupt("invalid key wait")),
+        };
+
+        let hires = input.bool()?;
+        let planes = input.u8()?;
+        let max_pixel = (1 << PLANES) - 1;
+        if planes > max_pixel {
+            return Err(StateError::Corrupt("invalid bitplanes"));
+        }
+        let mut pixels = [[0; HIRES_WIDTH]; HIRES_HEIGHT];
+        for row in pixels.iter_mut() {
+            *row = input.array()?;
+            if row.iter().any(|pixel| *pixel > max_pixel) {
+                return Err(StateError::Corrupt("invalid pixel"));
+            }
+        }
+
+        let saved = input.u32()? as usize;
+        if saved != memory_size {
+            return Err(StateError::MemorySizeMismatch {
+                saved,
+                system: memory_size,
+            });
+        }
+        let memory = input.take(saved)?.to_vec();
+        if !input.0.is_empty() {
+            return Err(StateError::Corrupt("trailing data"));
+        }
+
+        Ok(Self {
+            cycles,
+            frame,
+            frame_cycles,
+            cpu,
+            delay,
+            sound,
+            pitch,
+            pattern: has_pattern.then_some(pattern),
+            keys,
+            key_wait,
+            display: DisplayState {
+                pixels,
+                hires,
+                planes,
+            },
+            memory,
+        })
+    }
+}
+
+pub(crate) struct Writer(pub Vec<u8>);
+
+impl Writer {
+    pub fn u8(&mut self, value: u8) {
+        self.0.push(value);
+    }
+
+    pub fn bool(&mut self, value: bool) {
+        self.0.push(value as u8);
+    }
+
+    pub fn u16(&mut self, value: u16) {
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u32(&mut self, value: u32) {
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, value: u64) {
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn bytes(&mut self, bytes: &[u8]) {
+        self.0.extend_from_slice(bytes);
+    }
+}
+
+pub(crate) struct Reader<'a>(pub &'a [u8]);
+
+impl<'a> Reader<'a> {
+    pub fn take(&mut self, len: usize) -> Result<&'a [u8], StateError> {
+        if self.0.len() < len {
+            return Err(StateError::Corrupt("truncated"));
+        }
+        let (bytes, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(bytes)
+    }
+
+    pub fn array<const N: usize>(&mut self) -> Result<[u8; N], StateError> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+
+    pub fn u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn bool(&mut self) -> Result<bool, StateError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(StateError::Corrupt("invalid flag")),
+        }
+    }
+
+    pub fn u16(&mut self) -> Result<u16, StateError> {
+        Ok(u16::from_le_bytes(self.array()?))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, StateError> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, StateError> {
+        Ok(u64::from_le_bytes(self.array()?))
+    }
+}
+
+/// CRC-32 with the IEEE polynomial (as used by zip and PNG)
+pub(crate) fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in bytes {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{assembled, assembled_with};
+    use crate::{Config, Quirks, System};
+
+    fn saved() -> Vec<u8> {
+        let mut system = assembled(
+            "LD V0 5\nSTDT V0\nRND V1 0xFF\nCALL sub\nHALT\nsub: LDSPR V0\nDRW V0 V0 5\nloop: JP loop",
+        );
+        system.key_down(0xA);
+        system.step_n(8).unwrap();
+        system.save_state()
+    }
+
+    fn error(state: &[u8]) -> StateError {
+        assembled("").load_state(state).unwrap_err()
+    }
+
+    #[test]
+    fn save_load_save_is_identical() {
+        let state = saved();
+        let mut system = assembled("");
+        system.load_state(&state).unwrap();
+        assert_eq!(system.save_state(), state);
+    }
+
+    #[test]
+    fn corrupt_states_are_rejected() {
+        let mut flipped = saved();
+        flipped[HEADER_LEN + 20] ^= 0x01;
+        assert!(matches!(
+            error(&flipped),
+            StateError::Corrupt("checksum mismatch")
+        ));
+
+        let state = saved();
+        let truncated = &state[..state.len() - 1];
+        assert!(matches!(
+            error(truncated),
+            StateError::Corrupt("wrong length")
+        ));
+        assert!(matches!(error(&state[..6]), StateError::NotAState));
+    }
+
+    #[test]
+    fn foreign_states_are_rejected() {
+        let mut magic = saved();
+        magic[0] = b'X';
+        assert!(matches!(error(&magic), StateError::NotAState));
+
+        let mut future = saved();
+        future[4..6].copy_from_slice(&(VERSION + 1).to_le_bytes());
+        assert!(matches!(
+            error(&future),
+            StateError::UnsupportedVersion(version) if version == VERSION + 1
+        ));
+    }
+
+    fn awaiting_key(quirks: Quirks) -> System {
+        let config = Config {
+            quirks,
+            ..Config::default()
+        };
+        let mut system = assembled_with(config, "LDK V0\nHALT");
+        system.step().unwrap();
+        assert!(system.cpu().is_awaiting_key());
+        system
+    }
+
+    /// Saves and loads the system into a new one.
+    fn reload(system: &System) -> System {
+        let mut reloaded = assembled_with(*system.config(), "");
+        reloaded.load_state(&system.save_state()).unwrap();
+        reloaded
+    }
+
+    #[test]
+    fn key_wait_survives_press_then_release() {
+        let mut system = awaiting_key(Quirks::default());
+        system.key_down(0x5);
+        system.step().unwrap();
+        let mut system = reload(&system);
+        assert!(system.cpu().is_awaiting_key());
+        system.key_up(0x5);
+        let mut system = reload(&system);
+        system.step().unwrap();
+        assert!(!system.cpu().is_awaiting_key());
+        assert_eq!(system.cpu().get_register(0), 0x5);
+    }
+
+    #[test]
+    fn key_wait_on_press_survives_the_press() {
+        let quirks = Quirks {
+            key_wait_on_press: true,
+            ..Quirks::default()
+        };
+        let mut system = awaiting_key(quirks);
+        system.key_down(0x9);
+        let mut system = reload(&system);
+        system.step().unwrap();
+        assert!(!system.cpu().is_awaiting_key());
+        assert_eq!(system.cpu().get_register(0), 0x9);
+    }
+}
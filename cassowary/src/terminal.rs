@@ -0,0 +1,374 @@
+//! Interactive frontend that draws the display in the terminal and feeds
+//! key presses to the keypad.
+//!
+//! The display is drawn in place on the alternate screen, rewriting only the
+//! cells that changed since the previous frame. Input is read in raw mode, so
+//! it works over SSH. Most terminals only report key presses; a key then
+//! counts as held for [`KEY_HOLD`] after its last press or auto-repeat.
+//! Terminals supporting the kitty keyboard protocol also report releases.
+//!
+//! Frames run at the pace of a [`Scheduler`], which Space pauses and resumes,
+//! `+` and `-` speed up and slow down, and Tab switches to and from running
+//! as fast as possible. Host keys mapped to the keypad take precedence.
+
+use std::io::{self, Stdout, Write};
+use std::time::{Duration, Instant};
+
+use clap::ValueEnum;
+use crossterm::event::{
+    self, Event, KeyCode, KeyEventKind, KeyModifiers, KeyboardEnhancementFlags,
+    PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags,
+};
+use crossterm::style::{Color, Colors, Print, ResetColor, SetColors};
+use crossterm::terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::{cursor, execute, queue};
+
+use cassowary::scheduler::Scheduler;
+use cassowary::{Display, KeyMap, System, KEY_COUNT};
+
+/// Slowest and fastest speeds reached with `-` and `+`
+const MIN_SPEED: f64 = 1.0 / 8.0;
+const MAX_SPEED: f64 = 8.0;
+
+/// How long a key counts as held after a press when the terminal does not
+/// report releases; longer than the usual auto-repeat interval
+const KEY_HOLD: Duration = Duration::from_millis(250);
+
+/// Colors of the pixel values: off, plane 1, plane 2, both planes
+const PALETTE: [Color; 4] = [Color::Black, Color::White, Color::Yellow, Color::DarkYellow];
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Glyphs {
+    /// One character per pixel, no colors
+    Ascii,
+    /// Two pixels (one above the other) per character
+    HalfBlock,
+    /// Eight pixels (2x4) per character
+    Braille,
+}
+
+impl Glyphs {
+    /// Pixels per character cell, horizontally and vertically
+    fn cell_size(self) -> (usize, usize) {
+        match self {
+            Glyphs::Ascii => (1, 1),
+            Glyphs::HalfBlock => (1, 2),
+            Glyphs::Braille => (2, 4),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Cell {
+    glyph: char,
+    fg: Color,
+    bg: Color,
+}
+
+/// Runs frames until Esc (or Ctrl-C) is pressed. After the CPU halts the last
+/// frame stays on screen until then.
+pub fn run(
+    system: &mut System,
+    scheduler: &mut Scheduler,
+    glyphs: Glyphs,
+    keymap: KeyMap,
+) -> Result<(), String> {
+    let mut screen = Screen::open(glyphs).map_err(|err| err.to_string())?;
+    let mut input = Input::new(keymap, screen.reports_releases);
+    let help = format!(
+        "Esc: quit  Space: pause  +/-/Tab: speed  keys: {}",
+        layout(&keymap)
+    );
+    loop {
+        if input
+            .poll(system, scheduler, &mut screen)
+            .map_err(|err| err.to_string())?
+        {
+            return Ok(());
+        }
+        if system.cpu().is_halted() {
+            // nothing left to run, but keep reading input at 60 Hz
+            scheduler.pause();
+        }
+        scheduler
+            .run_frame(system)
+            .map_err(|err| format!("{} (PC: {:03X})", err, system.cpu().pc()))?;
+        let status = match state(system, scheduler) {
+            Some(state) => format!("{}  ({})", help, state),
+            None => help.clone(),
+        };
+        screen
+            .draw(system.display(), &status)
+            .map_err(|err| err.to_string())?;
+    }
+}
+
+/// What the status line says about the run, if it is not at normal speed.
+fn state(system: &System, scheduler: &Scheduler) -> Option<String> {
+    let speed = scheduler.speed();
+    if system.cpu().is_halted() {
+        Some("halted".to_string())
+    } else if scheduler.is_paused() {
+        Some("paused".to_string())
+    } else if speed.is_infinite() {
+        Some("fast-forward".to_string())
+    } else if speed != 1.0 {
+        Some(format!("speed x{}", speed))
+    } else {
+        None
+    }
+}
+
+/// The host keys in keypad order, one group per keypad row.
+fn layout(keymap: &KeyMap) -> String {
+    let rows: Vec<String> = [
+        [0x1, 0x2, 0x3, 0xC],
+        [0x4, 0x5, 0x6, 0xD],
+        [0x7, 0x8, 0x9, 0xE],
+        [0xA, 0x0, 0xB, 0xF],
+    ]
+    .iter()
+    .map(|row| row.iter().map(|key| keymap.host_key(*key)).collect())
+    .collect();
+    rows.join("/")
+}
+
+/// The terminal in raw mode on the alternate screen, restored when dropped.
+struct Screen {
+    out: Stdout,
+    glyphs: Glyphs,
+    /// what is currently on screen, row by row; empty to redraw everything
+    cells: Vec<Cell>,
+    status: String,
+    reports_releases: bool,
+}
+
+impl Screen {
+    fn open(glyphs: Glyphs) -> io::Result<Self> {
+        terminal::enable_raw_mode()?;
+        let mut out = io::stdout();
+        execute!(out, EnterAlternateScreen, cursor::Hide)?;
+        let reports_releases = terminal::supports_keyboard_enhancement().unwrap_or(false);
+        if reports_releases {
+            execute!(
+                out,
+                PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::REPORT_EVENT_TYPES)
+            )?;
+        }
+        Ok(Self {
+            out,
+            glyphs,
+            cells: Vec::new(),
+            status: String::new(),
+            reports_releases,
+        })
+    }
+
+    /// Forgets what is on screen, so the next draw redraws everything.
+    fn invalidate(&mut self) {
+        self.cells.clear();
+    }
+
+    fn draw(&mut self, display: &Display, status: &str) -> io::Result<()> {
+        let (cell_width, cell_height) = self.glyphs.cell_size();
+        let cols = display.width() / cell_width;
+        let rows = display.height() / cell_height;
+        let cells: Vec<Cell> = (0..rows)
+            .flat_map(|row| (0..cols).map(move |col| (col, row)))
+            .map(|(col, row)| self.cell(display, col, row))
+            .collect();
+
+        if cells.len() != self.cells.len() {
+            queue!(self.out, ResetColor, Clear(ClearType::All))?;
+            self.status.clear();
+        }
+        // skip cursor moves and color changes the terminal needs no telling
+        let mut at = None;
+        let mut colors = None;
+        for (n, cell) in cells.iter().enumerate() {
+            if self.cells.get(n) == Some(cell) {
+                continue;
+            }
+            if at != Some(n) {
+                queue!(
+                    self.out,
+                    cursor::MoveTo((n % cols) as u16, (n / cols) as u16)
+                )?;
+            }
+            if colors != Some((cell.fg, cell.bg)) {
+                queue!(self.out, SetColors(Colors::new(cell.fg, cell.bg)))?;
+                colors = Some((cell.fg, cell.bg));
+            }
+            queue!(self.out, Print(cell.glyph))?;
+            // the cursor does not move on to the next row by itself
+            at = ((n + 1) % cols != 0).then_some(n + 1);
+        }
+        self.cells = cells;
+
+        if self.status != status {
+            queue!(
+                self.out,
+                ResetColor,
+                cursor::MoveTo(0, rows as u16 + 1),
+                Clear(ClearType::CurrentLine),
+                Print(status)
+            )?;
+            self.status = status.to_string();
+        }
+        self.out.flush()
+    }
+
+    /// The character cell at (`col`, `row`) showing the pixels under it.
+    fn cell(&self, display: &Display, col: usize, row: usize) -> Cell {
+        match self.glyphs {
+            Glyphs::Ascii => Cell {
+                glyph: [' ', '*', '+', '#'][display.pixel(col, row) as usize & 0x03],
+                fg: Color::Reset,
+                bg: Color::Reset,
+            },
+            Glyphs::HalfBlock => Cell {
+                glyph: '▀',
+                fg: PALETTE[display.pixel(col, row * 2) as usize & 0x03],
+                bg: PALETTE[display.pixel(col, row * 2 + 1) as usize & 0x03],
+            },
+            Glyphs::Braille => {
+                // dot bits of the braille patterns, by row and column
+                const DOTS: [[u32; 2]; 4] =
+                    [[0x01, 0x08], [0x02, 0x10], [0x04, 0x20], [0x40, 0x80]];
+                let mut dots = 0;
+                let mut brightest = 0;
+                for (dy, bits) in DOTS.iter().enumerate() {
+                    for (dx, bit) in bits.iter().enumerate() {
+                        let pixel = display.pixel(col * 2 + dx, row * 4 + dy) & 0x03;
+                        if pixel != 0 {
+                            dots |= bit;
+                            brightest = brightest.max(pixel);
+                        }
+                    }
+                }
+                Cell {
+                    glyph: char::from_u32(0x2800 + dots).unwrap(),
+                    fg: PALETTE[brightest.max(1) as usize],
+                    bg: PALETTE[0],
+                }
+            }
+        }
+    }
+}
+
+impl Drop for Screen {
+    fn drop(&mut self) {
+        if self.reports_releases {
+            let _ = execute!(self.out, PopKeyboardEnhancementFlags);
+        }
+        let _ = execute!(self.out, ResetColor, cursor::Show, LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+struct Input {
+    keymap: KeyMap,
+    reports_releases: bool,
+    /// when each held key is released, if the terminal does not report it
+    release_at: [Option<Instant>; KEY_COUNT],
+    /// the speed to go back to when fast-forwarding
+    fast_forward: Option<f64>,
+}
+
+impl Input {
+    fn new(keymap: KeyMap, reports_releases: bool) -> Self {
+        Self {
+            keymap,
+            reports_releases,
+            release_at: [None; KEY_COUNT],
+            fast_forward: None,
+        }
+    }
+
+    /// Passes pending key events to the system and the scheduler, returning
+    /// `true` when the user asked to quit. Keypad keys are ignored while a
+    /// movie is replayed.
+    fn poll(
+        &mut self,
+        system: &mut System,
+        scheduler: &mut Scheduler,
+        screen: &mut Screen,
+    ) -> io::Result<bool> {
+        while event::poll(Duration::ZERO)? {
+            let key = match event::read()? {
+                Event::Key(key) => key,
+                Event::Resize(..) => {
+                    screen.invalidate();
+                    continue;
+                }
+                _ => continue,
+            };
+            let ctrl_c =
+                key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL);
+            if key.code == KeyCode::Esc || ctrl_c {
+                return Ok(true);
+            }
+            let pad = match key.code {
+                KeyCode::Char(host) => self.keymap.key(host),
+                _ => None,
+            };
+            let Some(pad) = pad else {
+                if key.kind == KeyEventKind::Press {
+                    self.control(key.code, scheduler);
+                }
+                continue;
+            };
+            if system.is_playing_movie() {
+                continue;
+            }
+            match key.kind {
+                KeyEventKind::Press | KeyEventKind::Repeat => {
+                    if !system.keyboard().is_down(pad) {
+                        system.key_down(pad);
+                    }
+                    if !self.reports_releases {
+                        self.release_at[pad as usize] = Some(Instant::now() + KEY_HOLD);
+                    }
+                }
+                KeyEventKind::Release => system.key_up(pad),
+            }
+        }
+
+        let now = Instant::now();
+        for (key, release_at) in self.release_at.iter_mut().enumerate() {
+            if release_at.is_some_and(|at| at <= now) {
+                *release_at = None;
+                system.key_up(key as u8);
+            }
+        }
+        Ok(false)
+    }
+
+    fn control(&mut self, code: KeyCode, scheduler: &mut Scheduler) {
+        match code {
+            KeyCode::Char(' ') if scheduler.is_paused() => scheduler.resume(),
+            KeyCode::Char(' ') => scheduler.pause(),
+            KeyCode::Char('+' | '=') => self.set_speed(scheduler, scheduler.speed() * 2.0),
+            KeyCode::Char('-') => self.set_speed(scheduler, scheduler.speed() / 2.0),
+            KeyCode::Tab => {
+                let speed = match self.fast_forward.take() {
+                    Some(speed) => speed,
+                    None => {
+                        self.fast_forward = Some(scheduler.speed());
+                        f64::INFINITY
+                    }
+                };
+                // infinity, or back to the speed before: always valid
+                let _ = scheduler.set_speed(speed);
+            }
+            _ => {}
+        }
+    }
+
+    /// Sets the speed within the range of `+` and `-`, ending any fast-forward.
+    fn set_speed(&mut self, scheduler: &mut Scheduler, speed: f64) {
+        self.fast_forward = None;
+        // the current speed doubled or halved, so never NaN
+        let _ = scheduler.set_speed(speed.clamp(MIN_SPEED, MAX_SPEED));
+    }
+}
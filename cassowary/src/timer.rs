@@ -1,23 +1,97 @@
-use std::sync::atomic::{AtomicU8, Ordering};
-use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
 
-use crossbeam_channel::{select, tick, unbounded, Receiver, Sender};
+use crossbeam_channel::{tick, Receiver, Sender};
 use std::thread;
 use std::time::{Duration, Instant};
 
 const TIMER_TICK: Duration = Duration::from_millis(16);
 
-pub(crate) struct Timer {
-    ticks: AtomicU8,
-    updates_tx: Sender<u8>,
-    updates_rx: Receiver<u8>,
+/// How the delay and sound timers are clocked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TimerMode {
+    /// Decremented once per emulated frame, so runs are reproducible.
+    #[default]
+    Emulated,
+    /// Decremented every 16 ms of wall-clock time by a background thread.
+    RealTime,
+}
+
+pub(crate) enum Timer {
+    Emulated {
+        ticks: u8,
+        changed: Option<Sender<u8>>,
+    },
+    RealTime(RealTimeTimer),
+}
+
+impl Timer {
+    pub(crate) fn new(mode: TimerMode, changed: Option<Sender<u8>>) -> Self {
+        match mode {
+            TimerMode::Emulated => Timer::Emulated { ticks: 0, changed },
+            TimerMode::RealTime => {
+                Timer::RealTime(RealTimeTimer(ThreadedTimer::start_new(TIMER_TICK, changed)))
+            }
+        }
+    }
+
+    /// Advances the timer by one emulated frame.
+    ///
+    /// Real-time timers are driven by their own thread, so this is a no-op for them.
+    pub(crate) fn tick(&mut self) {
+        if let Timer::Emulated { ticks, changed } = self {
+            if *ticks > 0 {
+                *ticks -= 1;
+                notify(changed, *ticks);
+            }
+        }
+    }
+
+    pub(crate) fn set(&mut self, value: u8) {
+        match self {
+            Timer::Emulated { ticks, changed } => {
+                *ticks = value;
+                notify(changed, *ticks);
+            }
+            Timer::RealTime(timer) => timer.0.set(value),
+        }
+    }
+
+    pub(crate) fn get(&self) -> u8 {
+        match self {
+            Timer::Emulated { ticks, .. } => *ticks,
+            Timer::RealTime(timer) => timer.0.get(),
+        }
+    }
+}
+
+fn notify(changed: &Option<Sender<u8>>, ticks: u8) {
+    if let Some(changed) = changed {
+        let _ = changed.send(ticks);
+    }
+}
+
+/// Owning handle of a threaded timer: stops the tick thread when dropped.
+pub(crate) struct RealTimeTimer(Arc<ThreadedTimer>);
+
+impl Drop for RealTimeTimer {
+    fn drop(&mut self) {
+        self.0.stopped.store(true, Ordering::Release);
+    }
+}
+
+pub(crate) struct ThreadedTimer {
+    /// locked while the value changes and the change is notified, so the
+    /// notifications arrive in order
+    ticks: Mutex<u8>,
+    stopped: AtomicBool,
     ticker: Receiver<Instant>,
     changed: Option<Sender<u8>>,
 }
 
-impl Timer {
+impl ThreadedTimer {
     pub(crate) fn start_new(tick_len: Duration, changed: Option<Sender<u8>>) -> Arc<Self> {
-        let timer = Arc::new(Timer::new(tick_len, changed));
+        let timer = Arc::new(ThreadedTimer::new(tick_len, changed));
         {
             let timer = Arc::clone(&timer);
             thread::spawn(move || timer.tick_loop());
@@ -26,66 +100,81 @@ impl Timer {
     }
 
     pub(crate) fn new(tick_len: Duration, changed: Option<Sender<u8>>) -> Self {
-        let (updates_tx, updates_rx) = unbounded();
         Self {
-            ticks: AtomicU8::new(0),
+            ticks: Mutex::new(0),
+            stopped: AtomicBool::new(false),
             ticker: tick(tick_len),
-            updates_tx,
-            updates_rx,
             changed,
         }
     }
 
     fn tick_loop(&self) {
-        loop {
-            select! {
-                recv(self.ticker) -> _tick => {
-                    let ticks = self.load_tick();
-                    if ticks > 0 {
-                        self.store_tick(ticks.saturating_sub(1));
-                    }
-                }
-                recv(self.updates_rx) -> new_ticks => {
-                    if let Ok(new_ticks) = new_ticks {
-                        self.store_tick(new_ticks);
-                    }
-                }
+        while !self.stopped.load(Ordering::Acquire) {
+            if self.ticker.recv().is_err() {
+                return;
+            }
+            let mut ticks = self.ticks.lock().unwrap();
+            if *ticks > 0 {
+                *ticks -= 1;
+                notify(&self.changed, *ticks);
             }
         }
     }
 
-    fn load_tick(&self) -> u8 {
-        self.ticks.load(Ordering::Acquire)
-    }
-
-    fn store_tick(&self, updated: u8) {
-        self.ticks.store(updated, Ordering::Release);
-        if let Some(ref changed) = self.changed {
-            changed.send(self.ticks.load(Ordering::SeqCst)).unwrap();
-        }
-    }
-
-    pub fn set(&self, ticks: u8) {
-        self.updates_tx.send(ticks).unwrap();
+    /// Sets the value, which [`ThreadedTimer::get`] returns right away.
+    pub fn set(&self, value: u8) {
+        let mut ticks = self.ticks.lock().unwrap();
+        *ticks = value;
+        notify(&self.changed, *ticks);
     }
 
     pub fn get(&self) -> u8 {
-        self.ticks.load(Ordering::Relaxed)
+        *self.ticks.lock().unwrap()
     }
 }
 
-pub struct DelayTimer(Arc<Timer>);
+pub struct DelayTimer(Timer);
 
 impl DelayTimer {
-    pub fn start_new() -> Self {
-        Self(Timer::start_new(TIMER_TICK, None))
+    pub fn new(mode: TimerMode) -> Self {
+        Self(Timer::new(mode, None))
     }
 
-    pub fn get(&mut self) -> u8 {
+    pub fn get(&self) -> u8 {
         self.0.get()
     }
 
     pub fn set(&mut self, value: u8) {
         self.0.set(value);
     }
+
+    /// Advances the timer by one emulated frame (see [`TimerMode::Emulated`]).
+    pub fn tick(&mut self) {
+        self.0.tick();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn emulated_timer_counts_down_to_zero() {
+        let mut timer = DelayTimer::new(TimerMode::Emulated);
+        timer.set(3);
+        for expected in [2, 1, 0, 0, 0] {
+            timer.tick();
+            assert_eq!(timer.get(), expected);
+        }
+    }
+
+    #[test]
+    fn real_time_timer_set_is_visible_at_once() {
+        let mut timer = DelayTimer::new(TimerMode::RealTime);
+        for value in [200, 0, 255] {
+            timer.set(value);
+            // a tick may come in between, but never more than one so soon
+            assert!(timer.get() == value || timer.get() == value.saturating_sub(1));
+        }
+    }
 }
@@ -0,0 +1,326 @@
+//! Execution trace log, see [`System::set_tracer`](crate::System::set_tracer).
+//!
+//! Each executed instruction is written as one entry with the cycle (number of
+//! instructions executed before it), PC, raw opcode, mnemonic, the registers it
+//! read (with their value before) and wrote (with their value after), `I` after
+//! execution and the memory it wrote. In JSON lines format an entry looks like:
+//!
+//! ```text
+//! {"cycle":3,"pc":516,"opcode":62259,"mnemonic":"STBCD V0","read":{"V0":123},"written":{},"i":528,"memory":[[528,1],[529,2],[530,3]]}
+//! ```
+
+use std::io::{self, Write};
+use std::ops::RangeInclusive;
+
+use crate::cpu::Step;
+use crate::instructions::{Instruction, InstructionClass, MemAddr, RegId};
+use crate::quirks::Quirks;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceFormat {
+    /// One human readable line per instruction
+    Text,
+    /// One JSON object per line
+    JsonLines,
+}
+
+/// A single traced instruction.
+#[derive(Debug, Clone)]
+pub struct TraceEntry {
+    pub cycle: u64,
+    pub step: Step,
+    /// Registers read, with their value before execution
+    pub read: Vec<(RegId, u8)>,
+    /// Registers written, with their value after execution
+    pub written: Vec<(RegId, u8)>,
+    pub index: MemAddr,
+    /// Memory written, as address and value
+    pub memory: Vec<(MemAddr, u8)>,
+}
+
+pub struct Tracer {
+    out: Box<dyn Write + Send>,
+    format: TraceFormat,
+    pc_range: Option<RangeInclusive<MemAddr>>,
+    classes: Option<Vec<InstructionClass>>,
+    /// first write error, after which nothing more is written
+    error: Option<io::Error>,
+}
+
+impl Tracer {
+    pub fn new(out: impl Write + Send + 'static, format: TraceFormat) -> Self {
+        Self {
+            out: Box::new(out),
+            format,
+            pc_range: None,
+            classes: None,
+            error: None,
+        }
+    }
+
+    /// Only traces instructions at addresses in `range`.
+    pub fn with_pc_range(mut self, range: RangeInclusive<MemAddr>) -> Self {
+        self.pc_range = Some(range);
+        self
+    }
+
+    /// Only traces instructions of the given classes.
+    pub fn with_classes(mut self, classes: &[InstructionClass]) -> Self {
+        self.classes = Some(classes.to_vec());
+        self
+    }
+
+    /// Returns `true` if the instruction executed in `step` passes the filters.
+    pub fn accepts(&self, step: &Step) -> bool {
+        let in_range = match &self.pc_range {
+            Some(range) => range.contains(&step.pc_before),
+            None => true,
+        };
+        let in_class = match &self.classes {
+            Some(classes) => classes.contains(&step.instruction.class()),
+            None => true,
+        };
+        in_range && in_class
+    }
+
+    /// Writes an entry, unless a previous write failed.
+    pub fn record(&mut self, entry: &TraceEntry) {
+        if self.error.is_some() {
+            return;
+        }
+        let result = match self.format {
+            TraceFormat::Text => write_text(&mut self.out, entry),
+            TraceFormat::JsonLines => write_json(&mut self.out, entry),
+        };
+        if let Err(err) = result {
+            self.error = Some(err);
+        }
+    }
+
+    /// Flushes the output, returning the first error that occurred while tracing.
+    pub fn finish(mut self) -> io::Result<()> {
+        match self.error.take() {
+            Some(err) => Err(err),
+            None => self.out.flush(),
+        }
+    }
+}
+
+fn write_text(out: &mut impl Write, entry: &TraceEntry) -> io::Result<()> {
+    let step = &entry.step;
+    let mnemonic = step.instruction.to_string();
+    write!(
+        out,
+        "{:8} {:03X}: {:04X}  {:24} I={:03X}",
+        entry.cycle, step.pc_before, step.opcode, mnemonic, entry.index
+    )?;
+    for (reg, value) in &entry.read {
+        write!(out, " V{:X}={:02X}", reg, value)?;
+    }
+    if !entry.written.is_empty() {
+        write!(out, " ->")?;
+    }
+    for (reg, value) in &entry.written {
+        write!(out, " V{:X}={:02X}", reg, value)?;
+    }
+    for (addr, value) in &entry.memory {
+        write!(out, " [{:03X}]={:02X}", addr, value)?;
+    }
+    writeln!(out)
+}
+
+/// Mnemonics contain no characters that need escaping in JSON strings.
+fn write_json(out: &mut impl Write, entry: &TraceEntry) -> io::Result<()> {
+    let step = &entry.step;
+    write!(
+        out,
+        "{{\"cycle\":{},\"pc\":{},\"opcode\":{},\"mnemonic\":\"{}\",\"read\":",
+        entry.cycle, step.pc_before, step.opcode, step.instruction
+    )?;
+    write_json_registers(out, &entry.read)?;
+    write!(out, ",\"written\":")?;
+    write_json_registers(out, &entry.written)?;
+    write!(out, ",\"i\":{},\"memory\":[", entry.index)?;
+    for (n, (addr, value)) in entry.memory.iter().enumerate() {
+        let sep = if n == 0 { "" } else { "," };
+        write!(out, "{}[{},{}]", sep, addr, value)?;
+    }
+    writeln!(out, "]}}")
+}
+
+fn write_json_registers(out: &mut impl Write, registers: &[(RegId, u8)]) -> io::Result<()> {
+    write!(out, "{{")?;
+    for (n, (reg, value)) in registers.iter().enumerate() {
+        let sep = if n == 0 { "" } else { "," };
+        write!(out, "{}\"V{:X}\":{}", sep, reg, value)?;
+    }
+    write!(out, "}}")
+}
+
+/// Returns the registers `step` read and the registers it wrote, as decoded
+/// from its instruction under `quirks`.
+pub(crate) fn registers_used(step: &Step, quirks: &Quirks) -> (Vec<RegId>, Vec<RegId>) {
+    const VF: RegId = 0xF;
+    let up_to = |x: RegId| (0..=x).collect::<Vec<_>>();
+    let between = |x: RegId, y: RegId| -> Vec<RegId> {
+        if x <= y {
+            (x..=y).collect()
+        } else {
+            (y..=x).rev().collect()
+        }
+    };
+    let (mut read, mut written) = match step.instruction {
+        Instruction::AssignXImm(x, _) | Instruction::GetDelayX(x) | Instruction::RandX(x, _) => {
+            (vec![], vec![x])
+        }
+        Instruction::AddXImm(x, _) => (vec![x], vec![x]),
+        Instruction::AssignXY(x, y) => (vec![y], vec![x]),
+        Instruction::OrXY(x, y) | Instruction::AndXY(x, y) | Instruction::XorXY(x, y) => {
+            if quirks.logic_resets_vf {
+                (vec![x, y], vec![x, VF])
+            } else {
+                (vec![x, y], vec![x])
+            }
+        }
+        Instruction::AddXY(x, y) | Instruction::SubXY(x, y) | Instruction::SubYX(x, y) => {
+            (vec![x, y], vec![x, VF])
+        }
+        Instruction::Shr1X(x, y) | Instruction::Shl1X(x, y) => {
+            let source = if quirks.shift_uses_vy { y } else { x };
+            (vec![source], vec![x, VF])
+        }
+        Instruction::DispDraw(x, y, _) => (vec![x, y], vec![VF]),
+        Instruction::SkipIfEqXY(x, y) | Instruction::SkipIfNeXY(x, y) => (vec![x, y], vec![]),
+        Instruction::SkipIfEqX(x, _)
+        | Instruction::SkipIfNeX(x, _)
+        | Instruction::SkipIfKeyEqX(x)
+        | Instruction::SkipIfKeyNeX(x)
+        | Instruction::SetDelayX(x)
+        | Instruction::SetSoundX(x)
+        | Instruction::SetPitchX(x)
+        | Instruction::SpriteAddrIX(x)
+        | Instruction::BigSpriteAddrIX(x)
+        | Instruction::DumpBcdIX(x) => (vec![x], vec![]),
+        Instruction::JumpV0(addr) => {
+            let reg = if quirks.jump_uses_vx {
+                (addr >> 8) & 0xF
+            } else {
+                0
+            };
+            (vec![reg], vec![])
+        }
+        // still waiting leaves VX unchanged
+        Instruction::AwaitKeyX(_) if step.awaiting_key => (vec![], vec![]),
+        Instruction::AwaitKeyX(x) => (vec![], vec![x]),
+        Instruction::AddIX(x) => {
+            if quirks.index_overflow_sets_vf {
+                (vec![x], vec![VF])
+            } else {
+                (vec![x], vec![])
+            }
+        }
+        Instruction::RegDumpIX(x) | Instruction::StoreFlagsX(x) => (up_to(x), vec![]),
+        Instruction::RegLoadIX(x) | Instruction::LoadFlagsX(x) => (vec![], up_to(x)),
+        Instruction::RegDumpRangeIXY(x, y) => (between(x, y), vec![]),
+        Instruction::RegLoadRangeIXY(x, y) => (vec![], between(x, y)),
+        Instruction::DispClear
+        | Instruction::ScrollDown(_)
+        | Instruction::ScrollUp(_)
+        | Instruction::ScrollRight
+        | Instruction::ScrollLeft
+        | Instruction::LoRes
+        | Instruction::HiRes
+        | Instruction::SelectPlanes(_)
+        | Instruction::Jump(_)
+        | Instruction::Call(_)
+        | Instruction::Ret
+        | Instruction::SetI(_)
+        | Instruction::SetILong(_)
+        | Instruction::LoadAudio
+        | Instruction::NoOp(_)
+        | Instruction::Halt
+        | Instruction::Exit
+        | Instruction::Unsupported(_) => (vec![], vec![]),
+    };
+    // e.g. `ADD V1 V1` reads V1 once and `ADD VF V1` writes VF once
+    read.dedup();
+    written.dedup();
+    (read, written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn step(instruction: Instruction) -> Step {
+        Step {
+            opcode: instruction.encode().unwrap(),
+            instruction,
+            pc_before: 0x200,
+            pc_after: 0x202,
+            display_changed: false,
+            awaiting_key: false,
+            halted: false,
+        }
+    }
+
+    #[test]
+    fn registers_follow_the_quirks() {
+        let shift = step(Instruction::Shr1X(1, 2));
+        let vip = registers_used(&shift, &Quirks::COSMAC_VIP);
+        assert_eq!(vip, (vec![2], vec![1, 0xF]));
+        let schip = registers_used(&shift, &Quirks::SUPER_CHIP);
+        assert_eq!(schip, (vec![1], vec![1, 0xF]));
+
+        let or = step(Instruction::OrXY(0xF, 0xF));
+        assert_eq!(
+            registers_used(&or, &Quirks::COSMAC_VIP),
+            (vec![0xF], vec![0xF])
+        );
+        let jump = step(Instruction::JumpV0(0x345));
+        assert_eq!(
+            registers_used(&jump, &Quirks::SUPER_CHIP),
+            (vec![3], vec![])
+        );
+    }
+
+    #[test]
+    fn registers_read_without_changing() {
+        let quirks = Quirks::default();
+        let store = step(Instruction::RegDumpIX(2));
+        assert_eq!(registers_used(&store, &quirks), (vec![0, 1, 2], vec![]));
+        let load = step(Instruction::RegLoadRangeIXY(3, 1));
+        assert_eq!(registers_used(&load, &quirks), (vec![], vec![3, 2, 1]));
+        let mut wait = step(Instruction::AwaitKeyX(4));
+        wait.awaiting_key = true;
+        assert_eq!(registers_used(&wait, &quirks), (vec![], vec![]));
+    }
+
+    #[test]
+    fn entries_list_read_and_written_registers() {
+        let entry = TraceEntry {
+            cycle: 7,
+            step: step(Instruction::AddXY(0, 1)),
+            read: vec![(0, 0x05), (1, 0x05)],
+            written: vec![(0, 0x0A), (0xF, 0x00)],
+            index: 0x300,
+            memory: vec![],
+        };
+        let mut text = Vec::new();
+        write_text(&mut text, &entry).unwrap();
+        assert_eq!(
+            String::from_utf8(text).unwrap(),
+            format!(
+                "{:8} 200: 8014  {:24} I=300 V0=05 V1=05 -> V0=0A VF=00\n",
+                7, "ADD V0 V1"
+            )
+        );
+        let mut json = Vec::new();
+        write_json(&mut json, &entry).unwrap();
+        assert_eq!(
+            String::from_utf8(json).unwrap(),
+            "{\"cycle\":7,\"pc\":512,\"opcode\":32788,\"mnemonic\":\"ADD V0 V1\",\
+             \"read\":{\"V0\":5,\"V1\":5},\"written\":{\"V0\":10,\"VF\":0},\"i\":768,\"memory\":[]}\n"
+        );
+    }
+}
@@ -0,0 +1,43 @@
+use std::process::{self, Command, Output};
+use std::{env, fs};
+
+/// Draws a digit, then loops forever
+const ROM: [u8; 8] = [0x60, 0x07, 0xF0, 0x29, 0xD0, 0x05, 0x12, 0x06];
+
+/// Runs the emulator with `args` followed by the path of [`ROM`].
+fn cassowary(name: &str, args: &[&str]) -> Output {
+    let rom = env::temp_dir().join(format!("cassowary-{}-{}.ch8", name, process::id()));
+    fs::write(&rom, ROM).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_cassowary"))
+        .args(args)
+        .arg(&rom)
+        .output()
+        .unwrap();
+    fs::remove_file(&rom).unwrap();
+    output
+}
+
+#[test]
+fn json_trace_on_stdout_parses() {
+    let output = cassowary("json", &["trace", "--format", "json", "--steps", "20"]);
+    assert!(output.status.success());
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(stdout.lines().count(), 20);
+    for line in stdout.lines() {
+        let entry: serde_json::Value =
+            serde_json::from_str(line).unwrap_or_else(|err| panic!("{}: {:?}", err, line));
+        assert!(entry.is_object());
+    }
+}
+
+#[cfg(feature = "terminal")]
+#[test]
+fn terminal_frontend_is_rejected_by_trace_and_debug() {
+    for command in ["trace", "debug"] {
+        let output = cassowary(command, &[command, "--frontend", "terminal"]);
+        assert!(!output.status.success());
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(stderr.contains("--frontend terminal"), "{}", stderr);
+    }
+}